@@ -0,0 +1,93 @@
+// Throughput of the compiler over large synthetic programs, through its public API. Run with
+// `cargo bench`; each stage reports its best time over a few runs and its throughput in MB of
+// source per second, which should hold steady across the sizes for a stage that is linear. The C
+// and bytecode stages include lexing and parsing, since `compile_str` always starts from source.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use teeny_tiny_compiler::{compile_str, parse_str, tokenize, Emit, Options};
+
+const SIZES: [usize; 3] = [1 << 20, 2 << 20, 5 << 20];
+const RUNS: usize = 3;
+
+// Builds a program of at least the given size.
+type Generator = fn(usize) -> String;
+
+// A valid program of at least `size` bytes, made of copies of a function that uses most of the
+// language (comments, escapes and non-ASCII text, arithmetic, conditions, loops and arrays) and
+// a call to each.
+fn program(size: usize) -> String {
+    let mut source = String::with_capacity(size + 1024);
+    source.push_str("LET total: FLOAT = 0\n");
+    let mut i = 0;
+    while source.len() < size {
+        source.push_str(&format!(
+            "# block {i}: ünïcödé comment\n\
+             FUNCTION f{i}(n: INT): FLOAT\n\
+             \x20 LET a = n * 2 + 3.5 / (1 - 4)\n\
+             \x20 LET s = \"text \\\"{i}\\\"\\t\\u{{e9}} é\"\n\
+             \x20 DIM values(4): INT\n\
+             \x20 IF a >= 10 AND LEN(s) != 0 OR NOT a < 0 THEN\n    PRINT s + \"!\"\n  ELSE\n    PRINT a\n  ENDIF\n\
+             \x20 FOR j = 1 TO 3 STEP 1\n    LET values(j) = values(j - 1) + j\n  NEXT\n\
+             \x20 RETURN a\n\
+             ENDFUNCTION\n\
+             LET total = total + f{i}({i})\n"
+        ));
+        i += 1;
+    }
+    source
+}
+
+// A valid program of at least `size` bytes whose main program has a new variable on every other
+// line, so scope lookups have to stay fast however many variables are visible.
+fn variables(size: usize) -> String {
+    let mut source = String::with_capacity(size + 64);
+    let mut i = 0;
+    while source.len() < size {
+        source.push_str(&format!("LET v{i} = {i}\nPRINT v{i} + 1\n"));
+        i += 1;
+    }
+    source
+}
+
+fn lex(source: &str) -> usize {
+    tokenize(source, "bench.teeny").unwrap().len()
+}
+
+fn parse(source: &str) -> teeny_tiny_compiler::ast::Program {
+    parse_str(source, "bench.teeny").unwrap()
+}
+
+fn compile(source: &str, emit: Emit) -> usize {
+    let options = Options { emit, file_name: String::from("bench.teeny"), ..Options::default() };
+    compile_str(source, &options).unwrap().as_bytes().len()
+}
+
+// The best of `RUNS` timings of `stage`.
+fn time<T>(mut stage: impl FnMut() -> T) -> Duration {
+    (0..RUNS).map(|_| {
+        let start = Instant::now();
+        black_box(stage());
+        start.elapsed()
+    }).min().unwrap()
+}
+
+fn report(shape: &str, stage: &str, bytes: usize, elapsed: Duration) {
+    let mb = bytes as f64 / (1 << 20) as f64;
+    println!("{:<10} {:<12} {:>5.1} MB {:>10.2} ms {:>8.1} MB/s", shape, stage, mb, elapsed.as_secs_f64() * 1000.0, mb / elapsed.as_secs_f64());
+}
+
+fn main() {
+    let shapes: [(&str, Generator); 2] = [("functions", program), ("variables", variables)];
+    for (shape, generate) in shapes {
+        for size in SIZES {
+            let source = generate(size);
+            report(shape, "lex", source.len(), time(|| lex(&source)));
+            report(shape, "parse", source.len(), time(|| parse(&source)));
+            report(shape, "to c", source.len(), time(|| compile(&source, Emit::C)));
+            report(shape, "to bytecode", source.len(), time(|| compile(&source, Emit::Bytecode)));
+            println!();
+        }
+    }
+}
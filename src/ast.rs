@@ -0,0 +1,341 @@
+use crate::enums::TokenKind;
+use crate::token::Span;
+
+// program ::= {function | statement}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+  pub functions: Vec<Function>,
+  // The top-level statements, which make up the main program.
+  pub body: Block
+}
+
+/// A `FUNCTION` declaration. Its parameters and the variables it assigns are local to it.
+/// Parameters and the result are FLOAT unless annotated, as in `FUNCTION f(n: INT): BOOL`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+  pub name: String,
+  pub params: Vec<Variable>,
+  pub ret: Type,
+  pub body: Block,
+  pub span: Span
+}
+
+/// The statements of a program, function or compound statement body, with the variables first
+/// assigned directly in it. Those start at 0 each time the block is entered and can't be used
+/// once it ends; a LET or INPUT of a name that is already visible assigns that variable instead
+/// of shadowing it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Block {
+  pub statements: Vec<Stmt>,
+  // Their types are worked out from the first assignments by `checker::check`.
+  pub locals: Vec<Variable>
+}
+
+/// A variable, or with `array` set an array made by DIM whose elements are of type `ty`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Variable {
+  pub name: String,
+  pub ty: Type,
+  pub array: bool
+}
+
+/// The type of a value. A variable gets one when it is first assigned, from its annotation or
+/// else from the value, and keeps it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+  Int,
+  Float,
+  Bool,
+  String
+}
+
+/// A statement. `span` is the span of the keyword that starts it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+  Print { value: PrintArg, span: Span },
+  // IF with any ELSEIFs as further branches, tried in order, then an optional ELSE.
+  If { branches: Vec<Branch>, else_body: Option<Block>, span: Span },
+  While { condition: Expr, body: Block, span: Span },
+  // The limit and step are evaluated once, on entry; without a STEP the variable counts up by 1.
+  // The variable and the hidden limit and step belong to the enclosing block.
+  For { name: String, start: Expr, end: Expr, step: Option<Expr>, body: Block, span: Span },
+  Label { name: String, span: Span },
+  Goto { name: String, span: Span },
+  // `ty` is the annotation in `LET x: INT = ...`, if there is one.
+  Let { name: String, ty: Option<Type>, value: Expr, span: Span },
+  Input { name: String, ty: Option<Type>, span: Span },
+  // `DIM name(size): ty` makes a new array of `size` elements, numbered from 0, each starting at 0.
+  // Executing it again replaces the array. Elements are FLOAT unless annotated.
+  Dim { name: String, size: Expr, ty: Type, span: Span },
+  // `LET name(index) = value`, assigning one element of an array.
+  LetIndex { name: String, index: Expr, value: Expr, span: Span },
+  Return { value: Expr, span: Span }
+}
+
+/// One `IF`/`ELSEIF` condition and the statements it guards. `span` is its keyword's span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+  pub condition: Expr,
+  pub body: Block,
+  pub span: Span
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrintArg {
+  Text(String),
+  Expr(Expr)
+}
+
+/// An expression. Operator nodes carry the span of their operator token.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+  Number { text: String, span: Span },
+  Bool { value: bool, span: Span },
+  // A string literal, without its quotes.
+  Text { text: String, span: Span },
+  Ident { name: String, span: Span },
+  // An element of an array, `name(index)`; `span` is the name's span.
+  Index { name: String, index: Box<Expr>, span: Span },
+  Unary { op: UnaryOp, operand: Box<Expr>, span: Span },
+  Binary { op: BinaryOp, lhs: Box<Expr>, rhs: Box<Expr>, span: Span },
+  Compare { op: CompareOp, lhs: Box<Expr>, rhs: Box<Expr>, span: Span },
+  // AND and OR only evaluate `rhs` when `lhs` doesn't already decide the result.
+  Logical { op: LogicalOp, lhs: Box<Expr>, rhs: Box<Expr>, span: Span },
+  Not { operand: Box<Expr>, span: Span },
+  // `LEN(s)`, the length of a STRING in bytes; `span` is the keyword's span.
+  Len { operand: Box<Expr>, span: Span },
+  // A call to a FUNCTION; `span` is the function name's span.
+  Call { name: String, args: Vec<Expr>, span: Span }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+  Plus,
+  Minus
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+  Add,
+  Sub,
+  Mul,
+  Div
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+  Eq,
+  NotEq,
+  Lt,
+  LtEq,
+  Gt,
+  GtEq
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogicalOp {
+  And,
+  Or
+}
+
+impl Stmt {
+  pub fn span(&self) -> Span {
+    match self {
+      Stmt::Print { span, .. }
+      | Stmt::If { span, .. }
+      | Stmt::While { span, .. }
+      | Stmt::For { span, .. }
+      | Stmt::Label { span, .. }
+      | Stmt::Goto { span, .. }
+      | Stmt::Let { span, .. }
+      | Stmt::Input { span, .. }
+      | Stmt::Dim { span, .. }
+      | Stmt::LetIndex { span, .. }
+      | Stmt::Return { span, .. } => *span
+    }
+  }
+
+  // The blocks nested directly inside this statement, in source order.
+  pub fn bodies(&self) -> Vec<&Block> {
+    match self {
+      Stmt::If { branches, else_body, .. } => {
+        let mut bodies: Vec<&Block> = branches.iter().map(|branch| &branch.body).collect();
+        bodies.extend(else_body);
+        bodies
+      },
+      Stmt::While { body, .. } | Stmt::For { body, .. } => vec![body],
+      _ => Vec::new()
+    }
+  }
+}
+
+/// The hidden variables that hold the limit and step of the FOR loop at `span`. Teeny
+/// identifiers can't contain `_`, so these never clash with a program's own variables.
+pub fn for_temporaries(span: Span) -> (String, String) {
+  (format!("_for{}_end", span.offset), format!("_for{}_step", span.offset))
+}
+
+impl Expr {
+  pub fn span(&self) -> Span {
+    match self {
+      Expr::Number { span, .. }
+      | Expr::Bool { span, .. }
+      | Expr::Text { span, .. }
+      | Expr::Ident { span, .. }
+      | Expr::Index { span, .. }
+      | Expr::Unary { span, .. }
+      | Expr::Binary { span, .. }
+      | Expr::Compare { span, .. }
+      | Expr::Logical { span, .. }
+      | Expr::Not { span, .. }
+      | Expr::Len { span, .. }
+      | Expr::Call { span, .. } => *span
+    }
+  }
+}
+
+impl Type {
+  /// The type named in an annotation.
+  pub fn from_name(name: &str) -> Option<Type> {
+    match name {
+      "INT" => Some(Type::Int),
+      "FLOAT" => Some(Type::Float),
+      "BOOL" => Some(Type::Bool),
+      "STRING" => Some(Type::String),
+      _ => None
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Type::Int => "INT",
+      Type::Float => "FLOAT",
+      Type::Bool => "BOOL",
+      Type::String => "STRING"
+    }
+  }
+
+  // A NUMBER literal is an INT unless it has a fraction or is too big for one, as in C.
+  pub fn of_literal(text: &str) -> Type {
+    if !text.contains('.') && text.parse::<i32>().is_ok() {
+      Type::Int
+    } else {
+      Type::Float
+    }
+  }
+
+  pub fn is_numeric(&self) -> bool {
+    matches!(self, Type::Int | Type::Float)
+  }
+
+  /// The type of arithmetic on these operands: INT only if both are, or `None` unless both are numbers.
+  pub fn arithmetic(lhs: Type, rhs: Type) -> Option<Type> {
+    match (lhs, rhs) {
+      (Type::Int, Type::Int) => Some(Type::Int),
+      _ if lhs.is_numeric() && rhs.is_numeric() => Some(Type::Float),
+      _ => None
+    }
+  }
+
+  /// The type of `lhs op rhs`: arithmetic on numbers, or `+` joining two STRINGs. `None` for
+  /// anything else.
+  pub fn binary(op: BinaryOp, lhs: Type, rhs: Type) -> Option<Type> {
+    match (op, lhs, rhs) {
+      (BinaryOp::Add, Type::String, Type::String) => Some(Type::String),
+      _ => Type::arithmetic(lhs, rhs)
+    }
+  }
+
+  /// Whether a value of type `value` can be stored in a variable of this type. INT widens to FLOAT;
+  /// nothing else converts.
+  pub fn accepts(&self, value: Type) -> bool {
+    *self == value || (*self == Type::Float && value == Type::Int)
+  }
+}
+
+impl UnaryOp {
+  pub fn from_token(kind: TokenKind) -> Option<UnaryOp> {
+    match kind {
+      TokenKind::PLUS => Some(UnaryOp::Plus),
+      TokenKind::MINUS => Some(UnaryOp::Minus),
+      _ => None
+    }
+  }
+
+  pub fn symbol(&self) -> &'static str {
+    match self {
+      UnaryOp::Plus => "+",
+      UnaryOp::Minus => "-"
+    }
+  }
+}
+
+impl BinaryOp {
+  pub fn from_token(kind: TokenKind) -> Option<BinaryOp> {
+    match kind {
+      TokenKind::PLUS => Some(BinaryOp::Add),
+      TokenKind::MINUS => Some(BinaryOp::Sub),
+      TokenKind::ASTERISK => Some(BinaryOp::Mul),
+      TokenKind::SLASH => Some(BinaryOp::Div),
+      _ => None
+    }
+  }
+
+  pub fn symbol(&self) -> &'static str {
+    match self {
+      BinaryOp::Add => "+",
+      BinaryOp::Sub => "-",
+      BinaryOp::Mul => "*",
+      BinaryOp::Div => "/"
+    }
+  }
+}
+
+impl CompareOp {
+  pub fn from_token(kind: TokenKind) -> Option<CompareOp> {
+    match kind {
+      TokenKind::EQEQ => Some(CompareOp::Eq),
+      TokenKind::NOTEQ => Some(CompareOp::NotEq),
+      TokenKind::LT => Some(CompareOp::Lt),
+      TokenKind::LTEQ => Some(CompareOp::LtEq),
+      TokenKind::GT => Some(CompareOp::Gt),
+      TokenKind::GTEQ => Some(CompareOp::GtEq),
+      _ => None
+    }
+  }
+
+  pub fn symbol(&self) -> &'static str {
+    match self {
+      CompareOp::Eq => "==",
+      CompareOp::NotEq => "!=",
+      CompareOp::Lt => "<",
+      CompareOp::LtEq => "<=",
+      CompareOp::Gt => ">",
+      CompareOp::GtEq => ">="
+    }
+  }
+}
+
+impl LogicalOp {
+  pub fn from_token(kind: TokenKind) -> Option<LogicalOp> {
+    match kind {
+      TokenKind::AND => Some(LogicalOp::And),
+      TokenKind::OR => Some(LogicalOp::Or),
+      _ => None
+    }
+  }
+
+  pub fn symbol(&self) -> &'static str {
+    match self {
+      LogicalOp::And => "&&",
+      LogicalOp::Or => "||"
+    }
+  }
+
+  pub fn keyword(&self) -> &'static str {
+    match self {
+      LogicalOp::And => "AND",
+      LogicalOp::Or => "OR"
+    }
+  }
+}
@@ -0,0 +1,777 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+use std::rc::Rc;
+
+use crate::ast::{self, BinaryOp, Block, CompareOp, Expr, LogicalOp, PrintArg, Program, Stmt, Type, UnaryOp, Variable};
+use crate::token::Span;
+use crate::value::Value;
+
+/// One instruction for the stack machine in `vm`. Operands index into the owning `Chunk`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+  // Push constants[index].
+  Constant(usize),
+  // Push the variable in a slot / pop into a slot, converting to the slot's type.
+  Load(usize),
+  Store(usize),
+  Negate,
+  // Replace a string with its length.
+  Length,
+  Add,
+  Subtract,
+  Multiply,
+  Divide,
+  Compare(CompareOp),
+  // Continue at an absolute instruction index.
+  Jump(usize),
+  // Pop the condition and jump when it is zero.
+  JumpIfFalse(usize),
+  // Pop a value and print it.
+  Print,
+  // Print strings[index].
+  PrintText(usize),
+  // Pop a size and replace the array in a slot with a new one of that many zeros.
+  Dim(usize),
+  // Pop an index and push that element of the array in a slot.
+  LoadIndex(usize),
+  // Pop a value and an index and store the value in that element of the array in a slot.
+  StoreIndex(usize),
+  // Read a number or string into a slot.
+  Input(usize),
+  // Pop a function's arguments and enter functions[index] with them in its first slots.
+  Call(usize),
+  // Pop the return value, go back to the caller and push it there as the function's result type.
+  Return,
+  Halt
+}
+
+/// A compiled program: instructions plus the tables they refer to.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Chunk {
+  pub code: Vec<Op>,
+  // Source span of each instruction, for runtime errors.
+  pub spans: Vec<Span>,
+  pub constants: Vec<Value>,
+  pub strings: Vec<String>,
+  // The variable in each slot of the main program. Each slot starts at its type's zero.
+  pub slots: Vec<Variable>,
+  // The code of each function follows the main program's, in this order.
+  pub functions: Vec<Function>
+}
+
+/// A compiled FUNCTION. Each call gets fresh slots, the first `params` holding its arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+  pub name: String,
+  pub address: usize,
+  pub params: usize,
+  pub ret: Type,
+  pub slots: Vec<Variable>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodeError(pub String);
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "invalid bytecode: {}", self.0)
+  }
+}
+
+impl Error for DecodeError {}
+
+/// Lowers a parsed program to a `Chunk`. Structured statements become conditional jumps and
+/// every LABEL resolves to the address of the instruction that follows it.
+pub struct Compiler {
+  chunk: Chunk,
+  functions: HashMap<String, usize>,
+  // Slots, labels and pending GOTOs of the body being compiled. `slots` maps each variable in
+  // scope to its slot, out of all the slots in `slot_variables`, and `slot_indices` finds the
+  // slot already given to a variable of the same name and type.
+  slots: HashMap<String, usize>,
+  slot_variables: Vec<Variable>,
+  slot_indices: HashMap<Variable, usize>,
+  labels: HashMap<String, usize>,
+  // Jump instructions waiting for a label's address.
+  gotos: Vec<(usize, String)>
+}
+
+impl Compiler {
+  pub fn compile(program: &Program) -> Chunk {
+    let mut compiler = Compiler {
+      chunk: Chunk::default(),
+      functions: program.functions.iter().enumerate().map(|(index, function)| (function.name.clone(), index)).collect(),
+      slots: HashMap::new(),
+      slot_variables: Vec::new(),
+      slot_indices: HashMap::new(),
+      labels: HashMap::new(),
+      gotos: Vec::new()
+    };
+
+    compiler.chunk.slots = compiler.body(&program.body, &[]);
+    compiler.emit(Op::Halt, Span::default());
+
+    for function in program.functions.iter() {
+      let address = compiler.chunk.code.len();
+      let slots = compiler.body(&function.body, &function.params);
+      // Falling off the end returns 0.
+      let zero = compiler.constant(Value::zero(function.ret));
+      compiler.emit(Op::Constant(zero), function.span);
+      compiler.emit(Op::Return, function.span);
+      compiler.chunk.functions.push(Function {
+        name: function.name.clone(),
+        address,
+        params: function.params.len(),
+        ret: function.ret,
+        slots
+      });
+    }
+    compiler.chunk
+  }
+
+  // Compile the main program or a function body, returning its slots.
+  fn body(&mut self, body: &Block, params: &[Variable]) -> Vec<Variable> {
+    self.slots.clear();
+    self.labels.clear();
+    for param in params.iter() {
+      self.declare(param);
+    }
+
+    // The VM starts every slot at 0, so only nested blocks have to reset their variables.
+    for local in body.locals.iter() {
+      self.declare(local);
+    }
+    self.statements(&body.statements);
+
+    // The parser has already checked that every GOTO target is declared in the same body.
+    for (address, label) in std::mem::take(&mut self.gotos) {
+      self.chunk.code[address] = Op::Jump(self.labels[&label]);
+    }
+    self.slot_indices.clear();
+    std::mem::take(&mut self.slot_variables)
+  }
+
+  fn emit(&mut self, op: Op, span: Span) -> usize {
+    self.chunk.code.push(op);
+    self.chunk.spans.push(span);
+    self.chunk.code.len() - 1
+  }
+
+  // Give a variable coming into scope a slot. Variables of sibling blocks with the same name
+  // and type share one.
+  fn declare(&mut self, variable: &Variable) -> usize {
+    let slot = match self.slot_indices.get(variable) {
+      Some(&slot) => slot,
+      None => {
+        self.slot_variables.push(variable.clone());
+        self.slot_indices.insert(variable.clone(), self.slot_variables.len() - 1);
+        self.slot_variables.len() - 1
+      }
+    };
+    self.slots.insert(variable.name.clone(), slot);
+    slot
+  }
+
+  fn slot(&self, name: &str) -> usize {
+    self.slots[name]
+  }
+
+  fn constant(&mut self, value: Value) -> usize {
+    self.chunk.constants.push(value);
+    self.chunk.constants.len() - 1
+  }
+
+  // Compile a nested block, setting its variables to 0 each time it is entered.
+  fn block(&mut self, block: &Block) {
+    for local in block.locals.iter() {
+      let slot = self.declare(local);
+      if local.array {
+        // An array goes back to having no elements until its DIM runs.
+        let empty = self.constant(Value::Int(0));
+        self.emit(Op::Constant(empty), Span::default());
+        self.emit(Op::Dim(slot), Span::default());
+      } else {
+        let zero = self.constant(Value::zero(local.ty));
+        self.emit(Op::Constant(zero), Span::default());
+        self.emit(Op::Store(slot), Span::default());
+      }
+    }
+    self.statements(&block.statements);
+  }
+
+  fn statements(&mut self, statements: &[Stmt]) {
+    for statement in statements.iter() {
+      self.statement(statement);
+    }
+  }
+
+  fn statement(&mut self, statement: &Stmt) {
+    match statement {
+      Stmt::Print { value: PrintArg::Text(text), span } => {
+        self.chunk.strings.push(text.clone());
+        self.emit(Op::PrintText(self.chunk.strings.len() - 1), *span);
+      },
+      Stmt::Print { value: PrintArg::Expr(expr), span } => {
+        self.expression(expr);
+        self.emit(Op::Print, *span);
+      },
+      Stmt::If { branches, else_body, span } => {
+        // Each failed condition falls through to the next branch; a taken branch jumps past the rest.
+        let mut exits = Vec::new();
+        for (index, branch) in branches.iter().enumerate() {
+          self.expression(&branch.condition);
+          let skip = self.emit(Op::JumpIfFalse(0), branch.span);
+          self.block(&branch.body);
+          if index + 1 < branches.len() || else_body.is_some() {
+            exits.push(self.emit(Op::Jump(0), *span));
+          }
+          self.chunk.code[skip] = Op::JumpIfFalse(self.chunk.code.len());
+        }
+        if let Some(else_body) = else_body {
+          self.block(else_body);
+        }
+        for exit in exits {
+          self.chunk.code[exit] = Op::Jump(self.chunk.code.len());
+        }
+      },
+      Stmt::While { condition, body, span } => {
+        let start = self.chunk.code.len();
+        self.expression(condition);
+        let exit = self.emit(Op::JumpIfFalse(0), *span);
+        self.block(body);
+        self.emit(Op::Jump(start), *span);
+        self.chunk.code[exit] = Op::JumpIfFalse(self.chunk.code.len());
+      },
+      Stmt::For { name, start, end, step, body, span } => {
+        let (end_name, step_name) = ast::for_temporaries(*span);
+        let variable = self.slot(name);
+        self.expression(start);
+        self.emit(Op::Store(variable), *span);
+        let limit = self.slot(&end_name);
+        self.expression(end);
+        self.emit(Op::Store(limit), *span);
+        let stride = step.as_ref().map(|step| {
+          let stride = self.slot(&step_name);
+          self.expression(step);
+          self.emit(Op::Store(stride), *span);
+          stride
+        });
+
+        // Count up while variable <= limit, or down while variable >= limit for a negative step.
+        let start = self.chunk.code.len();
+        let exit = match stride {
+          None => {
+            self.emit(Op::Load(variable), *span);
+            self.emit(Op::Load(limit), *span);
+            self.emit(Op::Compare(CompareOp::LtEq), *span);
+            self.emit(Op::JumpIfFalse(0), *span)
+          },
+          Some(stride) => {
+            self.emit(Op::Load(stride), *span);
+            let zero = self.constant(Value::Int(0));
+            self.emit(Op::Constant(zero), *span);
+            self.emit(Op::Compare(CompareOp::GtEq), *span);
+            let down = self.emit(Op::JumpIfFalse(0), *span);
+            self.emit(Op::Load(variable), *span);
+            self.emit(Op::Load(limit), *span);
+            self.emit(Op::Compare(CompareOp::LtEq), *span);
+            let test = self.emit(Op::Jump(0), *span);
+            self.chunk.code[down] = Op::JumpIfFalse(self.chunk.code.len());
+            self.emit(Op::Load(variable), *span);
+            self.emit(Op::Load(limit), *span);
+            self.emit(Op::Compare(CompareOp::GtEq), *span);
+            self.chunk.code[test] = Op::Jump(self.chunk.code.len());
+            self.emit(Op::JumpIfFalse(0), *span)
+          }
+        };
+        self.block(body);
+        self.emit(Op::Load(variable), *span);
+        match stride {
+          None => {
+            let one = self.constant(Value::Int(1));
+            self.emit(Op::Constant(one), *span)
+          },
+          Some(stride) => self.emit(Op::Load(stride), *span)
+        };
+        self.emit(Op::Add, *span);
+        self.emit(Op::Store(variable), *span);
+        self.emit(Op::Jump(start), *span);
+        self.chunk.code[exit] = Op::JumpIfFalse(self.chunk.code.len());
+      },
+      Stmt::Label { name, .. } => {
+        self.labels.insert(name.clone(), self.chunk.code.len());
+      },
+      Stmt::Goto { name, span } => {
+        let address = self.emit(Op::Jump(0), *span);
+        self.gotos.push((address, name.clone()));
+      },
+      Stmt::Let { name, value, span, .. } => {
+        self.expression(value);
+        let slot = self.slot(name);
+        self.emit(Op::Store(slot), *span);
+      },
+      Stmt::Input { name, span, .. } => {
+        let slot = self.slot(name);
+        self.emit(Op::Input(slot), *span);
+      },
+      Stmt::Dim { name, size, .. } => {
+        self.expression(size);
+        self.emit(Op::Dim(self.slot(name)), size.span());
+      },
+      Stmt::LetIndex { name, index, value, .. } => {
+        self.expression(index);
+        self.expression(value);
+        self.emit(Op::StoreIndex(self.slot(name)), index.span());
+      },
+      Stmt::Return { value, span } => {
+        self.expression(value);
+        self.emit(Op::Return, *span);
+      }
+    }
+  }
+
+  fn expression(&mut self, expr: &Expr) {
+    match expr {
+      Expr::Number { text, span } => {
+        let index = self.constant(Value::from_literal(text));
+        self.emit(Op::Constant(index), *span);
+      },
+      Expr::Bool { value, span } => {
+        let index = self.constant(Value::Bool(*value));
+        self.emit(Op::Constant(index), *span);
+      },
+      Expr::Text { text, span } => {
+        let index = self.constant(Value::Str(Rc::from(text.as_str())));
+        self.emit(Op::Constant(index), *span);
+      },
+      Expr::Ident { name, span } => {
+        let slot = self.slot(name);
+        self.emit(Op::Load(slot), *span);
+      },
+      Expr::Index { name, index, .. } => {
+        self.expression(index);
+        self.emit(Op::LoadIndex(self.slot(name)), index.span());
+      },
+      Expr::Unary { op, operand, span } => {
+        self.expression(operand);
+        if *op == UnaryOp::Minus {
+          self.emit(Op::Negate, *span);
+        }
+      },
+      Expr::Binary { op, lhs, rhs, span } => {
+        self.expression(lhs);
+        self.expression(rhs);
+        let op = match op {
+          BinaryOp::Add => Op::Add,
+          BinaryOp::Sub => Op::Subtract,
+          BinaryOp::Mul => Op::Multiply,
+          BinaryOp::Div => Op::Divide
+        };
+        self.emit(op, *span);
+      },
+      Expr::Compare { op, lhs, rhs, span } => {
+        self.expression(lhs);
+        self.expression(rhs);
+        self.emit(Op::Compare(*op), *span);
+      },
+      Expr::Logical { op, lhs, rhs, span } => {
+        // Jump over `rhs` once `lhs` settles the result, leaving TRUE or FALSE.
+        let mut to_true = Vec::new();
+        let mut to_false = Vec::new();
+        self.expression(lhs);
+        match op {
+          LogicalOp::And => to_false.push(self.emit(Op::JumpIfFalse(0), *span)),
+          LogicalOp::Or => {
+            let next = self.emit(Op::JumpIfFalse(0), *span);
+            to_true.push(self.emit(Op::Jump(0), *span));
+            self.chunk.code[next] = Op::JumpIfFalse(self.chunk.code.len());
+          }
+        }
+        self.expression(rhs);
+        to_false.push(self.emit(Op::JumpIfFalse(0), *span));
+
+        for address in to_true {
+          self.chunk.code[address] = Op::Jump(self.chunk.code.len());
+        }
+        let truth = self.constant(Value::Bool(true));
+        self.emit(Op::Constant(truth), *span);
+        let end = self.emit(Op::Jump(0), *span);
+        for address in to_false {
+          self.chunk.code[address] = Op::JumpIfFalse(self.chunk.code.len());
+        }
+        let falsehood = self.constant(Value::Bool(false));
+        self.emit(Op::Constant(falsehood), *span);
+        self.chunk.code[end] = Op::Jump(self.chunk.code.len());
+      },
+      Expr::Not { operand, span } => {
+        self.expression(operand);
+        let falsehood = self.constant(Value::Bool(false));
+        self.emit(Op::Constant(falsehood), *span);
+        self.emit(Op::Compare(CompareOp::Eq), *span);
+      },
+      Expr::Len { operand, span } => {
+        self.expression(operand);
+        self.emit(Op::Length, *span);
+      },
+      Expr::Call { name, args, span } => {
+        for arg in args.iter() {
+          self.expression(arg);
+        }
+        self.emit(Op::Call(self.functions[name]), *span);
+      }
+    }
+  }
+}
+
+// Serialized layout, all integers little-endian u32:
+//   "TTBC" version
+//   constants: count, then per constant a type byte (see TYPES) and its value: i32 or f64 bits,
+//              a 0 or 1 byte, or a string as in strings
+//   strings:   count, then per string a byte length and UTF-8 bytes
+//   slots:     count, then per slot its name as in strings, a type byte and a 1 byte for an array
+//   functions: count, then per function its name, address, params, result type byte and slots
+//   code:      count, then per instruction an opcode byte, an operand and its span
+const MAGIC: &[u8; 4] = b"TTBC";
+const VERSION: u32 = 4;
+
+const TYPES: [Type; 4] = [Type::Int, Type::Float, Type::Bool, Type::String];
+
+const COMPARE_OPS: [CompareOp; 6] = [
+  CompareOp::Eq, CompareOp::NotEq, CompareOp::Lt, CompareOp::LtEq, CompareOp::Gt, CompareOp::GtEq
+];
+
+impl Op {
+  fn encode(&self) -> (u8, usize) {
+    match *self {
+      Op::Constant(index) => (0, index),
+      Op::Load(slot) => (1, slot),
+      Op::Store(slot) => (2, slot),
+      Op::Negate => (3, 0),
+      Op::Add => (4, 0),
+      Op::Subtract => (5, 0),
+      Op::Multiply => (6, 0),
+      Op::Divide => (7, 0),
+      Op::Compare(op) => (8, COMPARE_OPS.iter().position(|&candidate| candidate == op).unwrap()),
+      Op::Jump(address) => (9, address),
+      Op::JumpIfFalse(address) => (10, address),
+      Op::Print => (11, 0),
+      Op::PrintText(index) => (12, index),
+      Op::Input(slot) => (13, slot),
+      Op::Halt => (14, 0),
+      Op::Call(index) => (15, index),
+      Op::Return => (16, 0),
+      Op::Length => (17, 0),
+      Op::Dim(slot) => (18, slot),
+      Op::LoadIndex(slot) => (19, slot),
+      Op::StoreIndex(slot) => (20, slot)
+    }
+  }
+
+  fn decode(opcode: u8, operand: usize) -> Result<Op, DecodeError> {
+    Ok(match opcode {
+      0 => Op::Constant(operand),
+      1 => Op::Load(operand),
+      2 => Op::Store(operand),
+      3 => Op::Negate,
+      4 => Op::Add,
+      5 => Op::Subtract,
+      6 => Op::Multiply,
+      7 => Op::Divide,
+      8 => Op::Compare(*COMPARE_OPS.get(operand).ok_or_else(|| DecodeError(format!("unknown comparison {}", operand)))?),
+      9 => Op::Jump(operand),
+      10 => Op::JumpIfFalse(operand),
+      11 => Op::Print,
+      12 => Op::PrintText(operand),
+      13 => Op::Input(operand),
+      14 => Op::Halt,
+      15 => Op::Call(operand),
+      16 => Op::Return,
+      17 => Op::Length,
+      18 => Op::Dim(operand),
+      19 => Op::LoadIndex(operand),
+      20 => Op::StoreIndex(operand),
+      _ => return Err(DecodeError(format!("unknown opcode {}", opcode)))
+    })
+  }
+}
+
+impl Chunk {
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    write_u32(&mut bytes, VERSION);
+
+    write_u32(&mut bytes, self.constants.len() as u32);
+    for constant in self.constants.iter() {
+      write_type(&mut bytes, constant.ty());
+      match constant {
+        Value::Int(value) => bytes.extend_from_slice(&value.to_le_bytes()),
+        Value::Float(value) => bytes.extend_from_slice(&value.to_le_bytes()),
+        Value::Bool(value) => bytes.push(*value as u8),
+        Value::Str(value) => write_string(&mut bytes, value),
+        Value::Array(..) => unreachable!("arrays are made by DIM, never constants")
+      }
+    }
+
+    write_strings(&mut bytes, &self.strings);
+    write_variables(&mut bytes, &self.slots);
+
+    write_u32(&mut bytes, self.functions.len() as u32);
+    for function in self.functions.iter() {
+      write_string(&mut bytes, &function.name);
+      write_u32(&mut bytes, function.address as u32);
+      write_u32(&mut bytes, function.params as u32);
+      write_type(&mut bytes, function.ret);
+      write_variables(&mut bytes, &function.slots);
+    }
+
+    write_u32(&mut bytes, self.code.len() as u32);
+    for (op, span) in self.code.iter().zip(self.spans.iter()) {
+      let (opcode, operand) = op.encode();
+      bytes.push(opcode);
+      write_u32(&mut bytes, operand as u32);
+      for field in [span.offset, span.line, span.col, span.len] {
+        write_u32(&mut bytes, field as u32);
+      }
+    }
+    bytes
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Result<Chunk, DecodeError> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.take(4)? != MAGIC {
+      return Err(DecodeError(String::from("missing TTBC header")));
+    }
+    let version = reader.u32()?;
+    if version != VERSION {
+      return Err(DecodeError(format!("unsupported version {}", version)));
+    }
+
+    let mut chunk = Chunk::default();
+    for _ in 0..reader.u32()? {
+      let value = match reader.ty()? {
+        Type::Int => Value::Int(i32::from_le_bytes(reader.take(4)?.try_into().unwrap())),
+        Type::Float => Value::Float(f64::from_le_bytes(reader.take(8)?.try_into().unwrap())),
+        Type::Bool => Value::Bool(reader.take(1)?[0] != 0),
+        Type::String => Value::Str(Rc::from(reader.string()?))
+      };
+      chunk.constants.push(value);
+    }
+
+    chunk.strings = reader.strings()?;
+    chunk.slots = reader.variables()?;
+
+    for _ in 0..reader.u32()? {
+      chunk.functions.push(Function {
+        name: reader.string()?,
+        address: reader.u32()? as usize,
+        params: reader.u32()? as usize,
+        ret: reader.ty()?,
+        slots: reader.variables()?
+      });
+    }
+
+    for _ in 0..reader.u32()? {
+      let opcode = reader.take(1)?[0];
+      let operand = reader.u32()? as usize;
+      chunk.code.push(Op::decode(opcode, operand)?);
+      chunk.spans.push(Span {
+        offset: reader.u32()? as usize,
+        line: reader.u32()? as usize,
+        col: reader.u32()? as usize,
+        len: reader.u32()? as usize
+      });
+    }
+
+    if reader.pos != bytes.len() {
+      return Err(DecodeError(String::from("trailing bytes after code")));
+    }
+    chunk.validate()?;
+    Ok(chunk)
+  }
+
+  // The main program's code and then each function's, with the slot names it uses.
+  fn regions(&self) -> Vec<(Range<usize>, Option<&Function>)> {
+    let mut starts = vec![(0, None)];
+    starts.extend(self.functions.iter().map(|function| (function.address, Some(function))));
+    starts.iter().enumerate()
+      .map(|(index, &(start, function))| {
+        let end = starts.get(index + 1).map_or(self.code.len(), |&(next, _)| next);
+        (start..end, function)
+      })
+      .collect()
+  }
+
+  // Make sure every operand points inside its table and control never leaves the main program
+  // or function it is in, so the VM never indexes out of bounds.
+  fn validate(&self) -> Result<(), DecodeError> {
+    // Checked before the addresses are used to split up the code.
+    let mut previous = 0;
+    for function in self.functions.iter() {
+      if function.address > self.code.len() || function.address < previous {
+        return Err(DecodeError(format!("address {} of function {} out of range", function.address, function.name)));
+      }
+      previous = function.address;
+    }
+    for (region, function) in self.regions() {
+      if region.is_empty() {
+        return Err(DecodeError(String::from("functions out of order")));
+      }
+      let slots = function.map_or(&self.slots, |function| &function.slots);
+      if function.is_some_and(|function| function.params > slots.len()) {
+        return Err(DecodeError(String::from("function has more parameters than slots")));
+      }
+
+      for op in self.code[region.clone()].iter() {
+        let in_bounds = match *op {
+          Op::Constant(index) => index < self.constants.len(),
+          // Arrays are only ever used an element at a time, and other variables never are.
+          Op::Load(slot) | Op::Store(slot) | Op::Input(slot) => slots.get(slot).is_some_and(|slot| !slot.array),
+          Op::Dim(slot) | Op::LoadIndex(slot) | Op::StoreIndex(slot) => slots.get(slot).is_some_and(|slot| slot.array),
+          Op::Jump(address) | Op::JumpIfFalse(address) => region.contains(&address),
+          Op::PrintText(index) => index < self.strings.len(),
+          Op::Call(index) => index < self.functions.len(),
+          Op::Return => function.is_some(),
+          Op::Halt => function.is_none(),
+          _ => true
+        };
+        if !in_bounds {
+          return Err(DecodeError(format!("operand out of range in {}", op)));
+        }
+      }
+
+      let last = if function.is_some() { Op::Return } else { Op::Halt };
+      if self.code[region.end - 1] != last {
+        return Err(DecodeError(format!("code does not end in {}", last)));
+      }
+    }
+    Ok(())
+  }
+}
+
+fn write_strings(bytes: &mut Vec<u8>, table: &[String]) {
+  write_u32(bytes, table.len() as u32);
+  for text in table.iter() {
+    write_string(bytes, text);
+  }
+}
+
+fn write_string(bytes: &mut Vec<u8>, text: &str) {
+  write_u32(bytes, text.len() as u32);
+  bytes.extend_from_slice(text.as_bytes());
+}
+
+fn write_variables(bytes: &mut Vec<u8>, variables: &[Variable]) {
+  write_u32(bytes, variables.len() as u32);
+  for variable in variables.iter() {
+    write_string(bytes, &variable.name);
+    write_type(bytes, variable.ty);
+    bytes.push(variable.array as u8);
+  }
+}
+
+fn write_type(bytes: &mut Vec<u8>, ty: Type) {
+  bytes.push(TYPES.iter().position(|&candidate| candidate == ty).unwrap() as u8);
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: u32) {
+  bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  pos: usize
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+    if self.pos + len > self.bytes.len() {
+      return Err(DecodeError(String::from("unexpected end of input")));
+    }
+    let slice = &self.bytes[self.pos..self.pos + len];
+    self.pos += len;
+    Ok(slice)
+  }
+
+  fn u32(&mut self) -> Result<u32, DecodeError> {
+    Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn string(&mut self) -> Result<String, DecodeError> {
+    let len = self.u32()? as usize;
+    let text = std::str::from_utf8(self.take(len)?).map_err(|_| DecodeError(String::from("string is not UTF-8")))?;
+    Ok(String::from(text))
+  }
+
+  fn strings(&mut self) -> Result<Vec<String>, DecodeError> {
+    (0..self.u32()?).map(|_| self.string()).collect()
+  }
+
+  fn ty(&mut self) -> Result<Type, DecodeError> {
+    let tag = self.take(1)?[0];
+    TYPES.get(tag as usize).copied().ok_or_else(|| DecodeError(format!("unknown type {}", tag)))
+  }
+
+  fn variables(&mut self) -> Result<Vec<Variable>, DecodeError> {
+    (0..self.u32()?).map(|_| Ok(Variable { name: self.string()?, ty: self.ty()?, array: self.take(1)?[0] != 0 })).collect()
+  }
+}
+
+impl fmt::Display for Op {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Op::Constant(index) => write!(f, "CONSTANT {}", index),
+      Op::Load(slot) => write!(f, "LOAD {}", slot),
+      Op::Store(slot) => write!(f, "STORE {}", slot),
+      Op::Negate => write!(f, "NEGATE"),
+      Op::Length => write!(f, "LENGTH"),
+      Op::Add => write!(f, "ADD"),
+      Op::Subtract => write!(f, "SUBTRACT"),
+      Op::Multiply => write!(f, "MULTIPLY"),
+      Op::Divide => write!(f, "DIVIDE"),
+      Op::Compare(op) => write!(f, "COMPARE {}", op.symbol()),
+      Op::Jump(address) => write!(f, "JUMP {}", address),
+      Op::JumpIfFalse(address) => write!(f, "JUMP_IF_FALSE {}", address),
+      Op::Print => write!(f, "PRINT"),
+      Op::PrintText(index) => write!(f, "PRINT_TEXT {}", index),
+      Op::Input(slot) => write!(f, "INPUT {}", slot),
+      Op::Dim(slot) => write!(f, "DIM {}", slot),
+      Op::LoadIndex(slot) => write!(f, "LOAD_INDEX {}", slot),
+      Op::StoreIndex(slot) => write!(f, "STORE_INDEX {}", slot),
+      Op::Call(index) => write!(f, "CALL {}", index),
+      Op::Return => write!(f, "RETURN"),
+      Op::Halt => write!(f, "HALT")
+    }
+  }
+}
+
+// A readable listing, one instruction per line with constants and names resolved.
+impl fmt::Display for Chunk {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for (region, function) in self.regions() {
+      if let Some(function) = function {
+        let params: Vec<&str> = function.slots[..function.params].iter().map(|param| param.name.as_str()).collect();
+        writeln!(f, "{}({}):", function.name, params.join(", "))?;
+      }
+      let slots = function.map_or(&self.slots, |function| &function.slots);
+      for address in region {
+        let op = self.code[address];
+        write!(f, "{:04} {}", address, op)?;
+        match op {
+          Op::Constant(index) => write!(f, " ({:?})", self.constants[index])?,
+          Op::Load(slot) | Op::Store(slot) | Op::Input(slot) | Op::Dim(slot) | Op::LoadIndex(slot) | Op::StoreIndex(slot) => {
+            write!(f, " ({})", slots[slot].name)?
+          },
+          Op::PrintText(index) => write!(f, " ({:?})", self.strings[index])?,
+          Op::Call(index) => write!(f, " ({})", self.functions[index].name)?,
+          _ => {}
+        }
+        writeln!(f)?;
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+
+use crate::ast::{self, Block, CompareOp, Expr, PrintArg, Program, Stmt, Type, UnaryOp, Variable};
+use crate::error::{CompileError, Diagnostics};
+use crate::token::Span;
+
+/// Work out the type of every variable from its annotation or first assignment, and check that
+/// every operator, condition, assignment, argument and RETURN gets values of the types it needs.
+/// Fills in the types of each block's `locals`. Also rejects constant array indices that are out of
+/// bounds for an array DIMmed with a constant size.
+pub fn check(program: &mut Program, file: &str) -> Diagnostics {
+  let mut checker = Checker {
+    file,
+    functions: program.functions.iter()
+      .map(|function| (function.name.clone(), (function.params.clone(), function.ret)))
+      .collect(),
+    variables: HashMap::new(),
+    arrays: HashMap::new(),
+    function: None,
+    diagnostics: Diagnostics::default()
+  };
+
+  for function in program.functions.iter_mut() {
+    checker.variables = function.params.iter().map(|param| (param.name.clone(), param.ty)).collect();
+    checker.function = Some((function.name.clone(), function.ret));
+    checker.block(&mut function.body);
+  }
+  checker.variables.clear();
+  checker.arrays.clear();
+  checker.function = None;
+  checker.block(&mut program.body);
+
+  checker.diagnostics
+}
+
+struct Checker<'a> {
+  file: &'a str,
+  // Each function's parameters and result.
+  functions: HashMap<String, (Vec<Variable>, Type)>,
+  // The variables assigned so far that are still in scope. The parser has made sure a visible
+  // name is never reused, so a block's variables are simply dropped when it ends.
+  variables: HashMap<String, Type>,
+  // Which of those are arrays, with the size they were DIMmed with if it is a constant. An
+  // array's entry in `variables` is the type of its elements.
+  arrays: HashMap<String, Option<i64>>,
+  // The function being checked and its result type, or `None` in the main program.
+  function: Option<(String, Type)>,
+  diagnostics: Diagnostics
+}
+
+impl<'a> Checker<'a> {
+  fn error(&self, span: Span, message: String) -> CompileError {
+    CompileError::Semantic { file: String::from(self.file), span, message }
+  }
+
+  fn block(&mut self, block: &mut Block) {
+    for statement in block.statements.iter_mut() {
+      if let Err(err) = self.statement(statement) {
+        self.diagnostics.push(err);
+      }
+    }
+    for local in block.locals.iter_mut() {
+      // Every local is assigned in its block, but one whose first assignment had an error
+      // may have been left untyped.
+      local.ty = self.variables.remove(&local.name).unwrap_or(Type::Float);
+      local.array = self.arrays.remove(&local.name).is_some();
+    }
+  }
+
+  fn statement(&mut self, statement: &mut Stmt) -> Result<(), CompileError> {
+    match statement {
+      Stmt::Print { value: PrintArg::Text(_), .. } | Stmt::Label { .. } | Stmt::Goto { .. } => {},
+      Stmt::Print { value: PrintArg::Expr(expr), .. } => {
+        self.expression(expr)?;
+      },
+      Stmt::If { branches, else_body, .. } => {
+        for branch in branches.iter_mut() {
+          if let Err(err) = self.condition(&branch.condition) {
+            self.diagnostics.push(err);
+          }
+          self.block(&mut branch.body);
+        }
+        if let Some(else_body) = else_body {
+          self.block(else_body);
+        }
+      },
+      Stmt::While { condition, body, .. } => {
+        if let Err(err) = self.condition(condition) {
+          self.diagnostics.push(err);
+        }
+        self.block(body);
+      },
+      Stmt::For { name, start, end, step, body, span } => {
+        if let Err(err) = self.for_header(name, start, end, step.as_ref(), *span) {
+          self.diagnostics.push(err);
+        }
+        self.block(body);
+      },
+      Stmt::Let { name, ty, value, span } => {
+        self.scalar(name, *span)?;
+        // An annotated variable is usable in its own first value, where it is still 0.
+        if let Some(ty) = ty {
+          self.declare(name, *ty, value.span())?;
+        }
+        let value_type = match self.expression(value) {
+          Ok(value_type) => value_type,
+          Err(err) => {
+            self.variables.entry(name.clone()).or_insert(Type::Float);
+            return Err(err);
+          }
+        };
+        let ty = *self.variables.entry(name.clone()).or_insert(value_type);
+        if !ty.accepts(value_type) {
+          let msg = format!("Type mismatch: can't assign {} to {}, which is {}", value_type.name(), name, ty.name());
+          return Err(self.error(value.span(), msg));
+        }
+      },
+      Stmt::Input { name, ty, span } => {
+        self.scalar(name, *span)?;
+        if let Some(ty) = ty {
+          self.declare(name, *ty, *span)?;
+        }
+        let ty = *self.variables.entry(name.clone()).or_insert(Type::Float);
+        if ty == Type::Bool {
+          let msg = format!("INPUT can only read numbers and strings, but {} is {}", name, ty.name());
+          return Err(self.error(*span, msg));
+        }
+      },
+      Stmt::Dim { name, size, ty, span } => {
+        if self.variables.contains_key(name.as_str()) {
+          let msg = if self.arrays.contains_key(name.as_str()) {
+            format!("Array {} already has a DIM", name)
+          } else {
+            format!("Can't DIM {}, which is already a variable", name)
+          };
+          return Err(self.error(*span, msg));
+        }
+        self.variables.insert(name.clone(), *ty);
+        self.arrays.insert(name.clone(), None);
+
+        let size_type = self.expression(size)?;
+        if size_type != Type::Int {
+          return Err(self.error(size.span(), format!("Type mismatch: array size must be INT, got {}", size_type.name())));
+        }
+        if let Some(count) = constant(size) {
+          if count < 0 {
+            return Err(self.error(size.span(), format!("Array size can't be negative: {}", count)));
+          }
+          self.arrays.insert(name.clone(), Some(count));
+        }
+      },
+      Stmt::LetIndex { name, index, value, span } => {
+        let ty = self.element(name, index, *span)?;
+        let value_type = self.expression(value)?;
+        if !ty.accepts(value_type) {
+          let msg = format!("Type mismatch: can't assign {} to an element of {}, which holds {}", value_type.name(), name, ty.name());
+          return Err(self.error(value.span(), msg));
+        }
+      },
+      Stmt::Return { value, .. } => {
+        let value_type = self.expression(value)?;
+        let (function, ret) = self.function.clone().expect("the parser only allows RETURN in a FUNCTION");
+        if !ret.accepts(value_type) {
+          let msg = format!("Type mismatch: {} must return {}, got {}", function, ret.name(), value_type.name());
+          return Err(self.error(value.span(), msg));
+        }
+      }
+    }
+    Ok(())
+  }
+
+  // Give a new variable the annotated type, or check the annotation matches an existing one.
+  fn declare(&mut self, name: &str, ty: Type, span: Span) -> Result<(), CompileError> {
+    match self.variables.get(name) {
+      Some(&existing) if existing != ty => {
+        let msg = format!("Variable {} already has type {}", name, existing.name());
+        Err(self.error(span, msg))
+      },
+      Some(_) => Ok(()),
+      None => {
+        self.variables.insert(String::from(name), ty);
+        Ok(())
+      }
+    }
+  }
+
+  // The variable, limit and step must all be numbers. A new variable is a FLOAT if any of them
+  // is, and the hidden limit and step share its type.
+  fn for_header(&mut self, name: &str, start: &Expr, end: &Expr, step: Option<&Expr>, span: Span) -> Result<(), CompileError> {
+    self.scalar(name, span)?;
+    let mut bounds = vec![start, end];
+    bounds.extend(step);
+    let mut types = Vec::new();
+    for bound in bounds.iter() {
+      let ty = self.expression(bound)?;
+      if !ty.is_numeric() {
+        let msg = format!("Type mismatch: FOR needs numbers, got {}", ty.name());
+        return Err(self.error(bound.span(), msg));
+      }
+      types.push(ty);
+    }
+
+    let inferred = types.iter().copied().reduce(|a, b| Type::arithmetic(a, b).unwrap()).unwrap();
+    let ty = *self.variables.entry(String::from(name)).or_insert(inferred);
+    for (bound, &bound_type) in bounds.iter().zip(types.iter()) {
+      if !ty.accepts(bound_type) {
+        let msg = format!("Type mismatch: can't assign {} to {}, which is {}", bound_type.name(), name, ty.name());
+        return Err(self.error(bound.span(), msg));
+      }
+    }
+
+    let (end_name, step_name) = ast::for_temporaries(span);
+    self.variables.insert(end_name, ty);
+    if step.is_some() {
+      self.variables.insert(step_name, ty);
+    }
+    Ok(())
+  }
+
+  // An error if `name` is an array, which can only be used an element at a time.
+  fn scalar(&self, name: &str, span: Span) -> Result<(), CompileError> {
+    if self.arrays.contains_key(name) {
+      return Err(self.error(span, format!("{} is an array; use one element at a time, as in {}(0)", name, name)));
+    }
+    Ok(())
+  }
+
+  // The type of element `index` of the array `name`.
+  fn element(&mut self, name: &str, index: &Expr, span: Span) -> Result<Type, CompileError> {
+    let Some(&size) = self.arrays.get(name) else {
+      return Err(self.error(span, format!("{} is not an array; declare one with DIM {}(size)", name, name)));
+    };
+    let index_type = self.expression(index)?;
+    if index_type != Type::Int {
+      return Err(self.error(index.span(), format!("Type mismatch: array index must be INT, got {}", index_type.name())));
+    }
+    if let (Some(size), Some(index_value)) = (size, constant(index)) {
+      if index_value < 0 || index_value >= size {
+        let msg = format!("Index {} is out of bounds for {}, whose size is {}", index_value, name, size);
+        return Err(self.error(index.span(), msg));
+      }
+    }
+    Ok(self.variables[name])
+  }
+
+  fn condition(&mut self, condition: &Expr) -> Result<(), CompileError> {
+    let ty = self.expression(condition)?;
+    if ty != Type::Bool {
+      let msg = format!("Type mismatch: condition must be BOOL, got {}", ty.name());
+      return Err(self.error(condition.span(), msg));
+    }
+    Ok(())
+  }
+
+  fn expression(&mut self, expr: &Expr) -> Result<Type, CompileError> {
+    match expr {
+      Expr::Number { text, .. } => Ok(Type::of_literal(text)),
+      Expr::Bool { .. } => Ok(Type::Bool),
+      Expr::Text { .. } => Ok(Type::String),
+      Expr::Ident { name, span } => match self.variables.get(name) {
+        Some(&ty) => {
+          self.scalar(name, *span)?;
+          Ok(ty)
+        },
+        // The parser has checked the name is in scope, so this is the variable's own first
+        // assignment, which can't decide its type.
+        None => {
+          let msg = format!("Can't infer the type of {} from a value that uses it; annotate it, as in LET {}: FLOAT", name, name);
+          Err(self.error(*span, msg))
+        }
+      },
+      Expr::Index { name, index, span } => self.element(name, index, *span),
+      Expr::Unary { op, operand, span } => {
+        let ty = self.expression(operand)?;
+        if !ty.is_numeric() {
+          return Err(self.error(*span, format!("Type mismatch: can't apply {} to {}", op.symbol(), ty.name())));
+        }
+        Ok(ty)
+      },
+      Expr::Binary { op, lhs, rhs, span } => {
+        let (lhs, rhs) = (self.expression(lhs)?, self.expression(rhs)?);
+        Type::binary(*op, lhs, rhs).ok_or_else(|| self.mismatch(op.symbol(), lhs, rhs, *span))
+      },
+      Expr::Compare { op, lhs, rhs, span } => {
+        let (lhs, rhs) = (self.expression(lhs)?, self.expression(rhs)?);
+        let equality = matches!(op, CompareOp::Eq | CompareOp::NotEq);
+        // Numbers can be ordered; BOOLs and STRINGs can only be tested for equality.
+        let comparable = (lhs.is_numeric() && rhs.is_numeric()) || (equality && lhs == rhs);
+        if !comparable {
+          return Err(self.mismatch(op.symbol(), lhs, rhs, *span));
+        }
+        Ok(Type::Bool)
+      },
+      Expr::Logical { op, lhs, rhs, span } => {
+        let (lhs, rhs) = (self.expression(lhs)?, self.expression(rhs)?);
+        if lhs != Type::Bool || rhs != Type::Bool {
+          return Err(self.mismatch(op.keyword(), lhs, rhs, *span));
+        }
+        Ok(Type::Bool)
+      },
+      Expr::Not { operand, span } => {
+        let ty = self.expression(operand)?;
+        if ty != Type::Bool {
+          return Err(self.error(*span, format!("Type mismatch: can't apply NOT to {}", ty.name())));
+        }
+        Ok(Type::Bool)
+      },
+      Expr::Len { operand, span } => {
+        let ty = self.expression(operand)?;
+        if ty != Type::String {
+          return Err(self.error(*span, format!("Type mismatch: LEN needs a STRING, got {}", ty.name())));
+        }
+        Ok(Type::Int)
+      },
+      Expr::Call { name, args, .. } => {
+        let (params, ret) = self.functions[name].clone();
+        for (index, (param, arg)) in params.iter().zip(args.iter()).enumerate() {
+          let ty = self.expression(arg)?;
+          if !param.ty.accepts(ty) {
+            let msg = format!("Type mismatch: argument {} of {} must be {}, got {}", index + 1, name, param.ty.name(), ty.name());
+            return Err(self.error(arg.span(), msg));
+          }
+        }
+        Ok(ret)
+      }
+    }
+  }
+
+  fn mismatch(&self, operator: &str, lhs: Type, rhs: Type, span: Span) -> CompileError {
+    self.error(span, format!("Type mismatch: can't apply {} to {} and {}", operator, lhs.name(), rhs.name()))
+  }
+}
+
+// The value of an INT literal, possibly signed, as in an array size or index.
+fn constant(expr: &Expr) -> Option<i64> {
+  match expr {
+    Expr::Number { text, .. } if Type::of_literal(text) == Type::Int => text.parse().ok(),
+    Expr::Unary { op: UnaryOp::Minus, operand, .. } => constant(operand).map(|value| -value),
+    Expr::Unary { op: UnaryOp::Plus, operand, .. } => constant(operand),
+    _ => None
+  }
+}
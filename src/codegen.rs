@@ -0,0 +1,781 @@
+use std::collections::HashMap;
+
+use crate::ast::{self, Block, Expr, Function, PrintArg, Program, Stmt, Type, Variable};
+use crate::emitter::{Emitter, Section};
+use crate::token::Span;
+
+/// Walks a parsed `Program` and emits the equivalent C through an `Emitter`.
+pub struct CGenerator<'a> {
+  emitter: &'a mut Emitter,
+  // The type of each variable in scope and the result type of each function, to pick the
+  // printf and scanf formats.
+  variables: HashMap<String, Type>,
+  functions: HashMap<String, Type>,
+  // The blocks open in the body being generated, innermost last, each with its number and the
+  // locals whose memory it frees when control leaves it; how many blocks of the body have been
+  // entered; and the numbers of the blocks around each of the body's labels.
+  blocks: Vec<(usize, Vec<Variable>)>,
+  block_count: usize,
+  label_blocks: HashMap<String, Vec<usize>>,
+  // Whether the program has STRING values other than the text of a PRINT, which needs
+  // `STRING_RUNTIME`, and whether it has arrays, which needs `ARRAY_RUNTIME`.
+  strings: bool,
+  arrays: bool,
+  // When set, array sizes and indices are checked at run time, and a bad one stops the program
+  // with an error naming its place in this file.
+  bounds_checks: Option<String>,
+  // When set, each statement, prototype and function signature is preceded by a `#line`
+  // directive naming this file so the C compiler reports problems against the teeny source.
+  source_file: Option<String>
+}
+
+impl<'a> CGenerator<'a> {
+  pub fn new(emitter: &'a mut Emitter) -> CGenerator<'a> {
+    CGenerator {
+      emitter,
+      variables: HashMap::new(),
+      functions: HashMap::new(),
+      blocks: Vec::new(),
+      block_count: 0,
+      label_blocks: HashMap::new(),
+      strings: false,
+      arrays: false,
+      bounds_checks: None,
+      source_file: None
+    }
+  }
+
+  pub fn with_line_directives(mut self, source_file: &str) -> CGenerator<'a> {
+    self.source_file = Some(escape(source_file));
+    self
+  }
+
+  pub fn with_bounds_checks(mut self, source_file: &str) -> CGenerator<'a> {
+    self.bounds_checks = Some(escape(source_file));
+    self
+  }
+
+  pub fn program(&mut self, program: &Program) {
+    self.functions = program.functions.iter().map(|function| (function.name.clone(), function.ret)).collect();
+    // Prototypes first, so functions can call each other whatever order they are declared in.
+    self.emitter.set_section(Section::Prototypes);
+    for function in program.functions.iter() {
+      self.line_directive(function.span);
+      self.emitter.emit_line(format!("{};", signature(function)));
+    }
+    self.emitter.set_section(Section::Functions);
+    for (index, function) in program.functions.iter().enumerate() {
+      if index > 0 {
+        self.emitter.emit_line("");
+      }
+      self.function(function);
+    }
+
+    self.emitter.set_section(Section::Main);
+    self.emitter.emit_line("int main(void){");
+    self.emitter.indent();
+    self.label_blocks = label_blocks(&program.body);
+    self.block(&program.body);
+    self.emitter.emit_line("return 0;");
+    self.emitter.dedent();
+    self.emitter.emit_line("}");
+
+    // The includes and runtime are picked last, once it is known what the program uses.
+    self.emitter.set_section(Section::Includes);
+    self.emitter.emit_line("#include <stdbool.h>");
+    self.emitter.emit_line("#include <stdio.h>");
+    if self.strings {
+      self.emitter.emit_line("#include <ctype.h>");
+    }
+    if self.strings || self.arrays {
+      self.emitter.emit_line("#include <stdlib.h>");
+    }
+    if self.strings {
+      self.emitter.emit_line("#include <string.h>");
+    }
+    self.emitter.set_section(Section::Globals);
+    let runtimes = [
+      (self.strings || self.arrays, ALLOC_RUNTIME),
+      (self.strings, STRING_RUNTIME),
+      (self.arrays, ARRAY_RUNTIME),
+      (self.arrays && self.strings, STRING_ARRAY_RUNTIME),
+      (self.arrays && self.bounds_checks.is_some(), BOUNDS_RUNTIME)
+    ];
+    for (index, (_, runtime)) in runtimes.iter().filter(|(needed, _)| *needed).enumerate() {
+      if index > 0 {
+        self.emitter.emit_line("");
+      }
+      self.emitter.emit_line(runtime);
+    }
+  }
+
+  // The C for a FUNCTION, written to the emitter's current section by a generator with the
+  // function's own variables.
+  fn function(&mut self, function: &Function) {
+    let mut generator = CGenerator {
+      emitter: &mut *self.emitter,
+      variables: function.params.iter().map(|param| (param.name.clone(), param.ty)).collect(),
+      // Lent for the duration; copying it for every function would be quadratic.
+      functions: std::mem::take(&mut self.functions),
+      blocks: Vec::new(),
+      block_count: 0,
+      label_blocks: label_blocks(&function.body),
+      strings: false,
+      arrays: false,
+      bounds_checks: self.bounds_checks.clone(),
+      source_file: self.source_file.clone()
+    };
+
+    generator.line_directive(function.span);
+    generator.emitter.emit_line(format!("{}{{", signature(function)));
+    generator.emitter.indent();
+    // The function owns its STRING arguments. They sit outside every block of the body, so only
+    // a RETURN or the end of the function frees them.
+    let owned: Vec<Variable> = function.params.iter().filter(|param| param.ty == Type::String).cloned().collect();
+    generator.strings |= !owned.is_empty() || function.ret == Type::String;
+    generator.blocks.push((usize::MAX, owned));
+    generator.block(&function.body);
+    let (_, owned) = generator.blocks.pop().expect("parameters pushed above");
+    if !ends_in_jump(&function.body) {
+      generator.release(&owned);
+    }
+    // Falling off the end returns 0, or its equivalent for the result type.
+    generator.emitter.emit_line(format!("return {};", zero(function.ret)));
+    generator.emitter.dedent();
+    generator.emitter.emit_line("}");
+    self.strings |= generator.strings;
+    self.arrays |= generator.arrays;
+    self.functions = generator.functions;
+  }
+
+  // Emit a block's statements, declaring its variables at the top of the matching C block.
+  // C locals start out with garbage, so each is zeroed as the interpreter and VM assume. An array
+  // is a pointer to its elements, with its size alongside, both set by its DIM. Arrays and
+  // STRINGs are freed when the block ends or is left by a GOTO or RETURN.
+  fn block(&mut self, block: &Block) {
+    // Attribute the declarations to the block's first statement rather than to whatever line
+    // came before.
+    if let Some(first) = block.statements.first().filter(|_| !block.locals.is_empty()) {
+      self.line_directive(first.span());
+    }
+    for local in block.locals.iter() {
+      self.variables.insert(local.name.clone(), local.ty);
+      self.strings |= local.ty == Type::String;
+      if local.array {
+        self.emitter.emit_line(format!("{}* {} = NULL;", c_type(local.ty), c_name(&local.name)));
+        self.emitter.emit_line(format!("int {}_size = 0;", c_name(&local.name)));
+      } else {
+        self.emitter.emit_line(format!("{} {} = {};", c_type(local.ty), c_name(&local.name), zero(local.ty)));
+      }
+    }
+    let owned = block.locals.iter().filter(|local| local.array || local.ty == Type::String).cloned().collect();
+    self.blocks.push((self.block_count, owned));
+    self.block_count += 1;
+    for statement in block.statements.iter() {
+      self.statement(statement);
+    }
+    let (_, owned) = self.blocks.pop().expect("block pushed above");
+    // A GOTO or RETURN at the end has already freed it all.
+    if !ends_in_jump(block) {
+      self.release(&owned);
+    }
+  }
+
+  // Free the memory held by `locals`.
+  fn release(&mut self, locals: &[Variable]) {
+    for local in locals.iter() {
+      let name = c_name(&local.name);
+      match (local.array, local.ty) {
+        (true, Type::String) => self.emitter.emit_line(format!("tt_free_strings({n}, {n}_size);", n = name)),
+        (true, _) => self.emitter.emit_line(format!("free({});", name)),
+        (false, _) => self.emitter.emit_line(format!("tt_release({});", name))
+      }
+    }
+  }
+
+  // A block that is the body of an if, loop or the like, indented inside its braces.
+  fn nested(&mut self, block: &Block) {
+    self.emitter.indent();
+    self.block(block);
+    self.emitter.dedent();
+  }
+
+  // Attribute the C that follows to `span`'s line, if line directives are on.
+  fn line_directive(&mut self, span: Span) {
+    if let Some(file) = &self.source_file {
+      self.emitter.emit_line(format!("#line {} \"{}\"", span.line, file));
+    }
+  }
+
+  pub fn statement(&mut self, statement: &Stmt) {
+    self.line_directive(statement.span());
+
+    match statement {
+      Stmt::Print { value: PrintArg::Text(text), .. } => {
+        self.emitter.emit_line(format!("puts(\"{}\");", escape(text)));
+      },
+      Stmt::Print { value: PrintArg::Expr(expr), .. } if self.expression_type(expr) == Type::String => {
+        self.emitter.emit(String::from("tt_print("));
+        self.string_argument(expr);
+        self.emitter.emit_line(String::from(");"));
+      },
+      Stmt::Print { value: PrintArg::Expr(expr), .. } => {
+        let ty = self.expression_type(expr);
+        let format = match ty {
+          Type::Int => "%d",
+          Type::Float => "%.2f",
+          Type::Bool | Type::String => "%s"
+        };
+        self.emitter.emit(format!("printf(\"{}\\n\", ", format));
+        if ty == Type::Bool {
+          // printf has no format for a bool, so print the words the interpreter does.
+          self.operand(expr);
+          self.emitter.emit(String::from(" ? \"true\" : \"false\""));
+        } else {
+          self.expression(expr);
+        }
+        self.emitter.emit_line(String::from(");"));
+      },
+      Stmt::If { branches, else_body, .. } => {
+        for (index, branch) in branches.iter().enumerate() {
+          self.emitter.emit(String::from(if index == 0 { "if(" } else { "}else if(" }));
+          self.expression(&branch.condition);
+          self.emitter.emit_line(String::from("){"));
+          self.nested(&branch.body);
+        }
+        if let Some(else_body) = else_body {
+          self.emitter.emit_line(String::from("}else{"));
+          self.nested(else_body);
+        }
+        self.emitter.emit_line(String::from("}"));
+      },
+      Stmt::While { condition, body, .. } => {
+        self.emitter.emit(String::from("while("));
+        self.expression(condition);
+        self.emitter.emit_line(String::from("){"));
+        self.nested(body);
+        self.emitter.emit_line(String::from("}"));
+      },
+      Stmt::For { name, start, end, step, body, span } => {
+        let name = c_name(name);
+        let (end_name, step_name) = ast::for_temporaries(*span);
+        let (end_name, step_name) = (c_name(&end_name), c_name(&step_name));
+        self.emitter.emit(format!("for({} = ", name));
+        self.expression(start);
+        self.emitter.emit(format!(", {} = ", end_name));
+        self.expression(end);
+        match step {
+          None => self.emitter.emit_line(format!("; {n} <= {e}; {n} = {n} + 1){{", n = name, e = end_name)),
+          Some(step) => {
+            // The direction of the test follows the sign of the step.
+            self.emitter.emit(format!(", {} = ", step_name));
+            self.expression(step);
+            self.emitter.emit_line(format!(
+              "; {s} >= 0 ? {n} <= {e} : {n} >= {e}; {n} = {n} + {s}){{",
+              n = name, e = end_name, s = step_name
+            ));
+          }
+        }
+        self.nested(body);
+        self.emitter.emit_line(String::from("}"));
+      },
+      Stmt::Label { name, .. } => {
+        self.emitter.emit_line(format!("{}: ;", c_name(name)));
+      },
+      Stmt::Goto { name, .. } => {
+        // Release the blocks being left, which are those the label isn't in.
+        let target = &self.label_blocks[name];
+        let leaving: Vec<Variable> = self.blocks.iter().rev()
+          .take_while(|(number, _)| !target.contains(number))
+          .flat_map(|(_, owned)| owned.iter().cloned())
+          .collect();
+        self.release(&leaving);
+        self.emitter.emit_line(format!("goto {};", c_name(name)));
+      },
+      Stmt::Let { name, value, .. } if self.variables[name] == Type::String => {
+        self.emitter.emit(format!("tt_assign(&{}, ", c_name(name)));
+        self.owned_string(value);
+        self.emitter.emit_line(String::from(");"));
+      },
+      Stmt::Let { name, value, .. } => {
+        self.emitter.emit(format!("{} = ", c_name(name)));
+        self.expression(value);
+        self.emitter.emit_line(String::from(";"));
+      },
+      Stmt::Input { name, .. } if self.variables[name] == Type::String => {
+        self.strings = true;
+        self.emitter.emit_line(format!("{n} = tt_input({n});", n = c_name(name)));
+      },
+      Stmt::Input { name, .. } => {
+        let format = if self.variables[name] == Type::Int { "%d" } else { "%lf" };
+        let name = c_name(name);
+        self.emitter.emit_line(format!("if(0 == scanf(\"{}\", &{})) {{", format, name));
+        self.emitter.indent();
+        self.emitter.emit_line(format!("{} = 0;", name));
+        self.emitter.emit_line(String::from("scanf(\"%*s\");"));
+        self.emitter.dedent();
+        self.emitter.emit_line(String::from("}"));
+      },
+      Stmt::Dim { name, size, ty, .. } => {
+        self.arrays = true;
+        let name = c_name(name);
+        // The size is worked out before the old elements are freed, as it may use them.
+        if *ty == Type::String {
+          self.emitter.emit(format!("{n} = tt_dim_strings({n}, &{n}_size, ", n = name));
+        } else {
+          self.emitter.emit(format!("{n} = tt_dim({n}, &{n}_size, ", n = name));
+        }
+        match self.bounds_checks.clone() {
+          Some(file) => {
+            self.emitter.emit(String::from("tt_size("));
+            self.expression(size);
+            self.emitter.emit(format!(", \"{}:{}\")", file, size.span()));
+          },
+          None => self.expression(size)
+        }
+        if *ty == Type::String {
+          self.emitter.emit_line(String::from(");"));
+        } else {
+          self.emitter.emit_line(format!(", sizeof *{});", name));
+        }
+      },
+      Stmt::LetIndex { name, index, value, .. } if self.variables[name] == Type::String => {
+        self.emitter.emit(String::from("tt_assign(&"));
+        self.element(name, index);
+        self.emitter.emit(String::from(", "));
+        self.owned_string(value);
+        self.emitter.emit_line(String::from(");"));
+      },
+      Stmt::LetIndex { name, index, value, .. } => {
+        self.element(name, index);
+        self.emitter.emit(String::from(" = "));
+        self.expression(value);
+        self.emitter.emit_line(String::from(";"));
+      },
+      Stmt::Return { value, .. } => {
+        let owned: Vec<Variable> = self.blocks.iter().rev().flat_map(|(_, owned)| owned.iter().cloned()).collect();
+        if owned.is_empty() {
+          self.emitter.emit(String::from("return "));
+          self.result(value);
+          self.emitter.emit_line(String::from(";"));
+        } else {
+          // The result may use what is about to be freed, so it is worked out first.
+          self.emitter.emit_line(String::from("{"));
+          self.emitter.indent();
+          self.emitter.emit(format!("{} tt_result = ", c_type(self.expression_type(value))));
+          self.result(value);
+          self.emitter.emit_line(String::from(";"));
+          self.release(&owned);
+          self.emitter.emit_line(String::from("return tt_result;"));
+          self.emitter.dedent();
+          self.emitter.emit_line(String::from("}"));
+        }
+      }
+    }
+  }
+
+  // Emit an expression. Every operand that is itself an operation is parenthesized, so the C
+  // groups exactly as the tree does whatever C's own precedence rules would say.
+  pub fn expression(&mut self, expr: &Expr) {
+    match expr {
+      // An integer too big for an INT is a FLOAT, so it has to be a double in the C too, not a long.
+      Expr::Number { text, .. } => self.emitter.emit(c_number(text)),
+      Expr::Bool { value, .. } => self.emitter.emit(value.to_string()),
+      Expr::Text { text, .. } => self.emitter.emit(format!("\"{}\"", escape(text))),
+      Expr::Ident { name, .. } => self.emitter.emit(c_name(name)),
+      Expr::Index { name, index, .. } => self.element(name, index),
+      Expr::Unary { op, operand, .. } => {
+        self.emitter.emit(String::from(op.symbol()));
+        self.operand(operand);
+      },
+      Expr::Binary { lhs, rhs, .. } if self.expression_type(lhs) == Type::String => {
+        self.strings = true;
+        self.emitter.emit(String::from("tt_concat("));
+        self.string_argument(lhs);
+        self.emitter.emit(String::from(", "));
+        self.string_argument(rhs);
+        self.emitter.emit(String::from(")"));
+      },
+      Expr::Binary { op, lhs, rhs, .. } => {
+        self.operand(lhs);
+        self.emitter.emit(String::from(op.symbol()));
+        self.operand(rhs);
+      },
+      Expr::Compare { op, lhs, rhs, .. } if self.expression_type(lhs) == Type::String => {
+        self.strings = true;
+        self.emitter.emit(String::from("tt_compare("));
+        self.string_argument(lhs);
+        self.emitter.emit(String::from(", "));
+        self.string_argument(rhs);
+        self.emitter.emit(format!("){}0", op.symbol()));
+      },
+      Expr::Compare { op, lhs, rhs, .. } => {
+        self.operand(lhs);
+        self.emitter.emit(String::from(op.symbol()));
+        self.operand(rhs);
+      },
+      Expr::Logical { op, lhs, rhs, .. } => {
+        self.operand(lhs);
+        self.emitter.emit(String::from(op.symbol()));
+        self.operand(rhs);
+      },
+      Expr::Not { operand, .. } => {
+        self.emitter.emit(String::from("!"));
+        self.operand(operand);
+      },
+      Expr::Len { operand, .. } => {
+        self.strings = true;
+        self.emitter.emit(String::from("tt_length("));
+        self.string_argument(operand);
+        self.emitter.emit(String::from(")"));
+      },
+      Expr::Call { name, args, .. } => {
+        self.emitter.emit(format!("{}(", function_name(name)));
+        for (index, arg) in args.iter().enumerate() {
+          if index > 0 {
+            self.emitter.emit(String::from(", "));
+          }
+          self.result(arg);
+        }
+        self.emitter.emit(String::from(")"));
+      }
+    }
+  }
+
+  // A STRING for a runtime helper, and whether the helper is to free it when done: a new STRING
+  // from a join or a FUNCTION has no other owner.
+  fn string_argument(&mut self, expr: &Expr) {
+    self.expression(expr);
+    self.emitter.emit(format!(", {}", is_new_string(expr)));
+  }
+
+  // A STRING for whoever receives it to own: a new one as it is, or a copy of any other.
+  fn owned_string(&mut self, expr: &Expr) {
+    if is_new_string(expr) {
+      self.expression(expr);
+    } else {
+      self.emitter.emit(String::from("tt_copy("));
+      self.expression(expr);
+      self.emitter.emit(String::from(")"));
+    }
+  }
+
+  // A value handed over to a FUNCTION or back from one, which owns it if it is a STRING.
+  fn result(&mut self, expr: &Expr) {
+    if self.expression_type(expr) == Type::String {
+      self.owned_string(expr);
+    } else {
+      self.expression(expr);
+    }
+  }
+
+  // `name[index]`, with the index checked against the array's size if bounds checks are on.
+  fn element(&mut self, name: &str, index: &Expr) {
+    match self.bounds_checks.clone() {
+      Some(file) => {
+        self.emitter.emit(format!("{}[tt_index(", c_name(name)));
+        self.expression(index);
+        self.emitter.emit(format!(", {}_size, \"{}\", \"{}:{}\")]", c_name(name), escape(name), file, index.span()));
+      },
+      None => {
+        self.emitter.emit(format!("{}[", c_name(name)));
+        self.expression(index);
+        self.emitter.emit(String::from("]"));
+      }
+    }
+  }
+
+  // The type of a checked expression.
+  fn expression_type(&self, expr: &Expr) -> Type {
+    match expr {
+      Expr::Number { text, .. } => Type::of_literal(text),
+      Expr::Bool { .. } | Expr::Compare { .. } | Expr::Logical { .. } | Expr::Not { .. } => Type::Bool,
+      Expr::Text { .. } => Type::String,
+      Expr::Len { .. } => Type::Int,
+      Expr::Ident { name, .. } | Expr::Index { name, .. } => self.variables[name],
+      Expr::Unary { operand, .. } => self.expression_type(operand),
+      Expr::Binary { op, lhs, rhs, .. } => {
+        Type::binary(*op, self.expression_type(lhs), self.expression_type(rhs)).expect("checked binary operands")
+      },
+      Expr::Call { name, .. } => self.functions[name]
+    }
+  }
+
+  fn operand(&mut self, expr: &Expr) {
+    match expr {
+      Expr::Number { .. } | Expr::Bool { .. } | Expr::Text { .. } | Expr::Ident { .. } | Expr::Index { .. } | Expr::Call { .. } => {
+        self.expression(expr)
+      },
+      _ => {
+        self.emitter.emit(String::from("("));
+        self.expression(expr);
+        self.emitter.emit(String::from(")"));
+      }
+    }
+  }
+}
+
+// Zeroed heap memory for strings and arrays, or the program stops if there is none to be had.
+const ALLOC_RUNTIME: &str = r#"void* tt_alloc(size_t count, size_t size){
+  void* memory = calloc(count > 0 ? count : 1, size);
+  if(memory == NULL){
+    fputs("out of memory\n", stderr);
+    exit(1);
+  }
+  return memory;
+}"#;
+
+// Helpers for the generated C's strings. A STRING is a `char*` that is never changed once made.
+// Each variable, element and parameter owns its own heap buffer, or `tt_empty`, and frees it when
+// it is replaced or goes out of scope. A new string from a join or a FUNCTION is handed to whoever
+// receives it, and any other is copied first. The helpers given a new string free it when done.
+const STRING_RUNTIME: &str = r#"char tt_empty[] = "";
+
+void tt_release(char* text){
+  if(text != tt_empty){
+    free(text);
+  }
+}
+
+char* tt_copy(const char* text){
+  size_t length = strlen(text);
+  char* result = tt_alloc(length + 1, 1);
+  memcpy(result, text, length + 1);
+  return result;
+}
+
+void tt_assign(char** variable, char* value){
+  tt_release(*variable);
+  *variable = value;
+}
+
+char* tt_concat(char* a, bool free_a, char* b, bool free_b){
+  size_t a_length = strlen(a);
+  size_t b_length = strlen(b);
+  char* result = tt_alloc(a_length + b_length + 1, 1);
+  memcpy(result, a, a_length);
+  memcpy(result + a_length, b, b_length + 1);
+  if(free_a){
+    tt_release(a);
+  }
+  if(free_b){
+    tt_release(b);
+  }
+  return result;
+}
+
+int tt_compare(char* a, bool free_a, char* b, bool free_b){
+  int result = strcmp(a, b);
+  if(free_a){
+    tt_release(a);
+  }
+  if(free_b){
+    tt_release(b);
+  }
+  return result;
+}
+
+int tt_length(char* text, bool free_text){
+  int length = (int)strlen(text);
+  if(free_text){
+    tt_release(text);
+  }
+  return length;
+}
+
+void tt_print(char* text, bool free_text){
+  puts(text);
+  if(free_text){
+    tt_release(text);
+  }
+}
+
+char* tt_input(char* old){
+  int c = getchar();
+  while(c != EOF && isspace(c)){
+    c = getchar();
+  }
+  if(c == EOF){
+    return old;
+  }
+  size_t length = 0;
+  size_t capacity = 16;
+  char* result = tt_alloc(capacity, 1);
+  while(c != EOF && c != '\n'){
+    if(length + 1 == capacity){
+      char* grown = tt_alloc(capacity * 2, 1);
+      memcpy(grown, result, length);
+      free(result);
+      result = grown;
+      capacity *= 2;
+    }
+    result[length++] = (char)c;
+    c = getchar();
+  }
+  result[length] = '\0';
+  tt_release(old);
+  return result;
+}"#;
+
+// Helpers for arrays. A DIM frees the array's old elements and makes new ones, all 0.
+const ARRAY_RUNTIME: &str = r#"void* tt_dim(void* old, int* size, int count, size_t element){
+  free(old);
+  *size = count;
+  return tt_alloc(count, element);
+}"#;
+
+// calloc's zero bytes make 0 and false, but a STRING element has to be `tt_empty`.
+const STRING_ARRAY_RUNTIME: &str = r#"void tt_free_strings(char** array, int size){
+  for(int i = 0; i < size; i++){
+    tt_release(array[i]);
+  }
+  free(array);
+}
+
+char** tt_dim_strings(char** old, int* size, int count){
+  tt_free_strings(old, *size);
+  *size = count;
+  char** array = tt_alloc(count, sizeof *array);
+  for(int i = 0; i < count; i++){
+    array[i] = tt_empty;
+  }
+  return array;
+}"#;
+
+// Bounds checks for arrays, which stop the program with the same message and exit status as a
+// runtime error in the interpreter.
+const BOUNDS_RUNTIME: &str = r#"int tt_size(int size, const char* where){
+  if(size < 0){
+    fflush(stdout);
+    fprintf(stderr, "error: %s: runtime error: array size can't be negative: %d\n", where, size);
+    exit(70);
+  }
+  return size;
+}
+
+int tt_index(int index, int size, const char* array, const char* where){
+  if(index < 0 || index >= size){
+    fflush(stdout);
+    fprintf(stderr, "error: %s: runtime error: index %d is out of bounds for %s, whose size is %d\n", where, index, array, size);
+    exit(70);
+  }
+  return index;
+}"#;
+
+// The numbers of the blocks around each label in `body`, outermost first, counting `body` as block
+// 0 and the blocks inside it in the order `CGenerator::block` enters them.
+fn label_blocks(body: &Block) -> HashMap<String, Vec<usize>> {
+  let mut labels = HashMap::new();
+  number_blocks(body, &mut Vec::new(), &mut 0, &mut labels);
+  labels
+}
+
+fn number_blocks(block: &Block, path: &mut Vec<usize>, count: &mut usize, labels: &mut HashMap<String, Vec<usize>>) {
+  path.push(*count);
+  *count += 1;
+  for statement in block.statements.iter() {
+    if let Stmt::Label { name, .. } = statement {
+      labels.insert(name.clone(), path.clone());
+    }
+    for body in statement.bodies() {
+      number_blocks(body, path, count, labels);
+    }
+  }
+  path.pop();
+}
+
+// `double name(int a, double b)`.
+fn signature(function: &Function) -> String {
+  let params: Vec<String> = function.params.iter().map(|param| format!("{} {}", c_type(param.ty), c_name(&param.name))).collect();
+  let params = if params.is_empty() { String::from("void") } else { params.join(", ") };
+  format!("{} {}({})", c_type(function.ret), function_name(&function.name), params)
+}
+
+// The C spelling of a number literal. Leading zeros go, as C would read `010` as octal, and an
+// integer too big for an int gets a `.0` so C makes it the double it is in teeny.
+fn c_number(text: &str) -> String {
+  let digits = text.trim_start_matches('0');
+  let mut number = match digits.chars().next() {
+    Some(c) if c.is_ascii_digit() => String::from(digits),
+    _ => format!("0{}", digits)
+  };
+  if Type::of_literal(text) == Type::Float && !text.contains('.') {
+    number.push_str(".0");
+  }
+  number
+}
+
+// The C identifier for a teeny variable or label. C's keywords and the headers' functions and
+// macros are too many to list, so every name gets a `v_` prefix. Teeny names can't contain `_`,
+// so these can't clash with the runtime's `tt_` names or with the `_size` of an array.
+fn c_name(name: &str) -> String {
+  mangle("v_", name)
+}
+
+// The C identifier for a FUNCTION. The headers declare far more functions than could be listed,
+// so every function gets an `f_` prefix rather than only the ones known to clash.
+fn function_name(name: &str) -> String {
+  mangle("f_", name)
+}
+
+// Whether control never reaches the end of `block`, as it ends in a GOTO or RETURN.
+fn ends_in_jump(block: &Block) -> bool {
+  matches!(block.statements.last(), Some(Stmt::Goto { .. } | Stmt::Return { .. }))
+}
+
+// Whether a STRING expression makes a new string, which nothing else owns.
+fn is_new_string(expr: &Expr) -> bool {
+  matches!(expr, Expr::Binary { .. } | Expr::Call { .. })
+}
+
+// `prefix` and `name`, with each character outside ASCII written as `_` and its hex code point and `_`.
+fn mangle(prefix: &str, name: &str) -> String {
+  let mut mangled = String::from(prefix);
+  for c in name.chars() {
+    if c.is_ascii() {
+      mangled.push(c);
+    } else {
+      mangled.push_str(&format!("_{:x}_", c as u32));
+    }
+  }
+  mangled
+}
+
+// The contents of a C string literal spelling `text`. Control characters are written as
+// three-digit octal escapes so a digit after them can't be read as part of the escape.
+fn escape(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  let mut previous = None;
+  for c in text.chars() {
+    match c {
+      '\\' => escaped.push_str("\\\\"),
+      '"' => escaped.push_str("\\\""),
+      '\n' => escaped.push_str("\\n"),
+      '\t' => escaped.push_str("\\t"),
+      // `??` followed by some characters is a trigraph in standard C.
+      '?' if previous == Some('?') => escaped.push_str("\\?"),
+      c if c.is_ascii_control() => escaped.push_str(&format!("\\{:03o}", c as u32)),
+      c => escaped.push(c)
+    }
+    previous = Some(c);
+  }
+  escaped
+}
+
+fn c_type(ty: Type) -> &'static str {
+  match ty {
+    Type::Int => "int",
+    Type::Float => "double",
+    Type::Bool => "bool",
+    Type::String => "char*"
+  }
+}
+
+// What a variable of type `ty` starts as, matching `Value::zero`.
+fn zero(ty: Type) -> &'static str {
+  match ty {
+    Type::Int | Type::Float => "0",
+    Type::Bool => "false",
+    Type::String => "tt_empty"
+  }
+}
@@ -1,50 +1,97 @@
-use std::fs::File;
-use std::path::Path;
-use std::io::prelude::*;
+use std::io;
 
+/// A part of the generated C. The sections are written out in this order, whatever order their
+/// code was emitted in, so the includes can be picked once the whole program has been seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+  Includes,
+  // Definitions the rest of the program uses, such as the runtime support functions.
+  Globals,
+  // A prototype for each function, so they can call each other in any order.
+  Prototypes,
+  Functions,
+  Main
+}
+
+const SECTIONS: [Section; 5] = [Section::Includes, Section::Globals, Section::Prototypes, Section::Functions, Section::Main];
+
+const INDENT: &str = "  ";
+
+/// Collects generated C in memory, one buffer per `Section`. Each line is indented to the current
+/// depth, except preprocessor lines, which stay at the left margin.
+#[derive(Debug, Default)]
 pub struct Emitter {
-  full_path: String,
-  header: String,
-  code: String
+  sections: [String; SECTIONS.len()],
+  current: usize,
+  depth: usize,
+  // Whether the last text emitted left a line unfinished, so the next text continues it rather
+  // than being indented.
+  mid_line: bool
 }
 
 impl Emitter {
-  pub fn build(full_path: String) -> Emitter {
-    let emitter = Emitter {
-      full_path,
-      header: String::from(""),
-      code: String::from("")
-    };
+  pub fn new() -> Emitter {
+    Emitter { current: Section::Main as usize, ..Default::default() }
+  }
 
-    emitter
+  /// Send what is emitted from now on to `section`.
+  pub fn set_section(&mut self, section: Section) {
+    self.current = section as usize;
   }
 
-  pub fn emit(&mut self, code: String) {
-    self.code += &code;
+  /// Indent the lines that follow one level deeper, as for the body of a block.
+  pub fn indent(&mut self) {
+    self.depth += 1;
   }
 
-  pub fn emit_line(&mut self, code: String) {
-    let new_code = format!("{}{}{}", self.code, code, '\n');
-    self.code = new_code;
+  pub fn dedent(&mut self) {
+    self.depth = self.depth.checked_sub(1).expect("dedent without a matching indent");
   }
 
-  pub fn header_line(&mut self, code: String) {
-    let new_header = format!("{}{}{}", self.header, code, '\n');
-    self.header = new_header;
+  /// Append `code` to the current section. Text may span lines; each new line is indented.
+  pub fn emit(&mut self, code: impl AsRef<str>) {
+    let section = &mut self.sections[self.current];
+    for (index, line) in code.as_ref().split('\n').enumerate() {
+      if index > 0 {
+        section.push('\n');
+        self.mid_line = false;
+      }
+      if line.is_empty() {
+        continue;
+      }
+      if !self.mid_line && !line.starts_with('#') {
+        for _ in 0..self.depth {
+          section.push_str(INDENT);
+        }
+      }
+      section.push_str(line);
+      self.mid_line = true;
+    }
   }
 
-  pub fn write_file(&mut self) {
-    let path = Path::new(&self.full_path);
-    let display = Path::display(&path);
+  pub fn emit_line(&mut self, code: impl AsRef<str>) {
+    self.emit(code);
+    self.emit("\n");
+  }
 
-    let mut file = match File::create(&path) {
-      Err(why) => panic!("couldn't create {}: {}", display, why),
-      Ok(file) => file,
-    };
+  /// The generated program: each section that has anything in it, in order, with a blank line
+  /// between them.
+  pub fn contents(&self) -> String {
+    let mut contents = Vec::new();
+    self.write_to(&mut contents).expect("writing to memory can't fail");
+    String::from_utf8(contents).expect("emitted code is built from strs")
+  }
 
-    match File::write_all(&mut file, (self.header.clone() + self.code.as_str()).as_bytes()) {
-      Err(why) => panic!("couldn't write to {}: {}", display, why),
-      Ok(..) => println!("successfully wrote to {}", display)
+  pub fn write_to(&self, mut out: impl io::Write) -> io::Result<()> {
+    for part in self.parts() {
+      out.write_all(part.as_bytes())?;
     }
+    out.flush()
+  }
+
+  fn parts(&self) -> impl Iterator<Item = &str> {
+    let mut sections = self.sections.iter().filter(|section| !section.is_empty());
+    let first = sections.next().map(String::as_str);
+    first.into_iter().chain(sections.flat_map(|section| ["\n", section.as_str()]))
   }
 }
@@ -1,3 +1,4 @@
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TokenKind {
    EOF = -1,
@@ -17,6 +18,22 @@ pub enum TokenKind {
    WHILE = 109,
    REPEAT = 110,
    ENDWHILE = 111,
+   ELSE = 112,
+   ELSEIF = 113,
+   FOR = 114,
+   TO = 115,
+   STEP = 116,
+   NEXT = 117,
+   AND = 118,
+   OR = 119,
+   NOT = 120,
+   FUNCTION = 121,
+   RETURN = 122,
+   ENDFUNCTION = 123,
+   TRUE = 124,
+   FALSE = 125,
+   LEN = 126,
+   DIM = 127,
    // Operators
    EQ = 201,
    PLUS = 202,
@@ -28,5 +45,9 @@ pub enum TokenKind {
    LT = 208,
    LTEQ = 209,
    GT = 210,
-   GTEQ = 211
+   GTEQ = 211,
+   LPAREN = 212,
+   RPAREN = 213,
+   COMMA = 214,
+   COLON = 215
 }
@@ -0,0 +1,136 @@
+use crate::lexer::LexingError;
+use crate::token::Span;
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::process::ExitStatus;
+
+#[derive(Debug)]
+pub enum CompileError {
+  Lexical { file: String, error: LexingError },
+  Syntax { file: String, span: Span, message: String },
+  Semantic { file: String, span: Span, message: String },
+  Runtime { file: String, span: Span, message: String },
+  // The system C compiler rejected the generated code.
+  CCompiler { command: String, stderr: String },
+  // A program built and run with `--run` failed on its own.
+  Exited { executable: String, status: ExitStatus },
+  Io(io::Error)
+}
+
+/// A failure while executing a program, such as integer division by zero.
+#[derive(Debug)]
+pub struct RuntimeError {
+  pub span: Span,
+  pub message: String
+}
+
+/// Every error found while compiling a program, in source order.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+  pub errors: Vec<CompileError>
+}
+
+impl CompileError {
+  pub fn runtime(file: &str, err: RuntimeError) -> CompileError {
+    CompileError::Runtime { file: String::from(file), span: err.span, message: err.message }
+  }
+
+  pub fn span(&self) -> Option<Span> {
+    match self {
+      CompileError::Lexical { error, .. } => Some(error.span),
+      CompileError::Syntax { span, .. }
+      | CompileError::Semantic { span, .. }
+      | CompileError::Runtime { span, .. } => Some(*span),
+      CompileError::CCompiler { .. } | CompileError::Exited { .. } | CompileError::Io(_) => None
+    }
+  }
+
+  /// Process exit code for this error, following the BSD `sysexits.h` conventions, except that
+  /// a program that failed under `--run` passes its own status through.
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      // A program killed by a signal has no status of its own.
+      CompileError::Exited { status, .. } => status.code().unwrap_or(70),
+      CompileError::Io(_) => 74,
+      CompileError::Runtime { .. } | CompileError::CCompiler { .. } => 70,
+      _ => 65
+    }
+  }
+}
+
+impl fmt::Display for CompileError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CompileError::Lexical { file, error } => write!(f, "{}:{}: lexical error: {}", file, error.span, error),
+      CompileError::Syntax { file, span, message } => write!(f, "{}:{}: syntax error: {}", file, span, message),
+      CompileError::Semantic { file, span, message } => write!(f, "{}:{}: semantic error: {}", file, span, message),
+      CompileError::Runtime { file, span, message } => write!(f, "{}:{}: runtime error: {}", file, span, message),
+      CompileError::CCompiler { command, stderr } => write!(f, "{} failed:\n{}", command, stderr.trim_end()),
+      CompileError::Exited { executable, status } => write!(f, "{} exited with {}", executable, status),
+      CompileError::Io(err) => write!(f, "I/O error: {}", err)
+    }
+  }
+}
+
+impl Error for CompileError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      CompileError::Io(err) => Some(err),
+      _ => None
+    }
+  }
+}
+
+impl From<io::Error> for CompileError {
+  fn from(err: io::Error) -> Self {
+    CompileError::Io(err)
+  }
+}
+
+impl Diagnostics {
+  pub fn push(&mut self, err: CompileError) {
+    self.errors.push(err);
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.errors.is_empty()
+  }
+
+  pub fn len(&self) -> usize {
+    self.errors.len()
+  }
+
+  pub fn iter(&self) -> std::slice::Iter<'_, CompileError> {
+    self.errors.iter()
+  }
+
+  pub fn sort(&mut self) {
+    self.errors.sort_by_key(|err| err.span().map(|span| span.offset));
+  }
+
+  pub fn exit_code(&self) -> i32 {
+    self.errors.iter().map(CompileError::exit_code).max().unwrap_or(0)
+  }
+}
+
+impl fmt::Display for Diagnostics {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for (index, err) in self.errors.iter().enumerate() {
+      if index > 0 {
+        writeln!(f)?;
+      }
+      write!(f, "{}", err)?;
+    }
+    Ok(())
+  }
+}
+
+impl Error for Diagnostics {}
+
+impl From<CompileError> for Diagnostics {
+  fn from(err: CompileError) -> Self {
+    Diagnostics { errors: vec![err] }
+  }
+}
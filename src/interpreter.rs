@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use crate::ast::{self, BinaryOp, Block, CompareOp, Expr, Function, LogicalOp, PrintArg, Program, Stmt};
+use crate::error::RuntimeError;
+use crate::token::Span;
+use crate::value::{self, Value};
+
+/// Executes a parsed program directly, producing the same output as the compiled C.
+pub struct Interpreter<R: BufRead, W: Write> {
+  input: R,
+  output: W,
+  // The variables of the running function, or of the main program outside any call.
+  variables: HashMap<String, Value>,
+  functions: HashMap<String, Rc<Callable>>,
+  // The address of the Rust stack when `run` started, to measure how much nested calls use.
+  stack_base: usize
+}
+
+/// How much of the Rust stack nested FUNCTION calls may use before the program is stopped with a
+/// stack overflow. Each call recurses through the tree, so this is measured in bytes rather than
+/// calls: a few hundred calls in a debug build, a few thousand in a release one. It leaves room
+/// for the deepest statement within a normal 8 MiB thread stack.
+const STACK_BUDGET: usize = 4 * 1024 * 1024;
+
+// Where each label lives: at each level of nesting, the statement index and which of that
+// statement's bodies (see `Stmt::bodies`) to descend into next.
+type Labels = HashMap<String, Vec<(usize, usize)>>;
+
+struct Callable {
+  function: Function,
+  labels: Labels
+}
+
+// How control leaves a statement: by falling through, by a GOTO that still has to find its
+// label, or by a RETURN that unwinds to the call.
+enum Flow {
+  Next,
+  Goto(String),
+  Return(Value)
+}
+
+impl<R: BufRead, W: Write> Interpreter<R, W> {
+  pub fn new(input: R, output: W) -> Interpreter<R, W> {
+    Interpreter {
+      input,
+      output,
+      variables: HashMap::new(),
+      functions: HashMap::new(),
+      stack_base: 0
+    }
+  }
+
+  pub fn run(&mut self, program: &Program) -> Result<(), RuntimeError> {
+    self.stack_base = stack_address();
+    self.functions = program.functions.iter()
+      .map(|function| {
+        let callable = Callable { function: function.clone(), labels: labels(&function.body) };
+        (function.name.clone(), Rc::new(callable))
+      })
+      .collect();
+
+    self.body(&program.body, &labels(&program.body))?;
+    self.output.flush().map_err(|err| io_error(Span::default(), err))
+  }
+
+  // Run the main program or a function body to completion.
+  fn body(&mut self, body: &Block, labels: &Labels) -> Result<Flow, RuntimeError> {
+    // A GOTO unwinds to the top and execution re-enters the tree at its label, which may sit
+    // inside any IF, WHILE or FOR body, just as a C goto can jump into a block. The parser only
+    // allows that for blocks without variables of their own, so there are none to set up.
+    let mut flow = self.enter(body)?;
+    while let Flow::Goto(label) = flow {
+      flow = self.resume(&body.statements, &labels[&label])?;
+    }
+    Ok(flow)
+  }
+
+  // Run a block from the top. Its variables start at 0 each time, like the C declarations.
+  fn enter(&mut self, block: &Block) -> Result<Flow, RuntimeError> {
+    for local in block.locals.iter() {
+      self.variables.insert(local.name.clone(), Value::initial(local));
+    }
+    self.block(&block.statements, 0)
+  }
+
+  // Store into a variable, converting to the type it already has.
+  fn assign(&mut self, name: &str, value: Value) {
+    let ty = self.variables[name].ty();
+    self.variables.insert(String::from(name), value.convert(ty));
+  }
+
+  fn block(&mut self, statements: &[Stmt], start: usize) -> Result<Flow, RuntimeError> {
+    for statement in statements[start..].iter() {
+      match self.statement(statement)? {
+        Flow::Next => {},
+        flow => return Ok(flow)
+      }
+    }
+    Ok(Flow::Next)
+  }
+
+  fn call(&mut self, name: &str, args: &[Expr], span: Span) -> Result<Value, RuntimeError> {
+    if stack_address().abs_diff(self.stack_base) > STACK_BUDGET {
+      return Err(RuntimeError { span, message: format!("stack overflow calling {}", name) });
+    }
+
+    let callable = Rc::clone(&self.functions[name]);
+    let mut locals = HashMap::new();
+    for (param, arg) in callable.function.params.iter().zip(args.iter()) {
+      locals.insert(param.name.clone(), self.expression(arg)?.convert(param.ty));
+    }
+
+    let caller = std::mem::replace(&mut self.variables, locals);
+    let flow = self.body(&callable.function.body, &callable.labels);
+    self.variables = caller;
+
+    // Like the C function, the result has the declared type, and is 0 if the body ends without a RETURN.
+    let ret = callable.function.ret;
+    match flow? {
+      Flow::Return(value) => Ok(value.convert(ret)),
+      _ => Ok(Value::zero(ret))
+    }
+  }
+
+  // Continue executing `statements` from just after the label at `path`, then carry on as if
+  // control had arrived there normally: finish the enclosing block and loop back for a WHILE.
+  fn resume(&mut self, statements: &[Stmt], path: &[(usize, usize)]) -> Result<Flow, RuntimeError> {
+    let (index, body_index) = path[0];
+    if path.len() > 1 {
+      let flow = match &statements[index] {
+        // Whichever branch the label is in, the rest of the IF is skipped afterwards.
+        statement @ Stmt::If { .. } => self.resume(&statement.bodies()[body_index].statements, &path[1..])?,
+        Stmt::While { condition, body, .. } => match self.resume(&body.statements, &path[1..])? {
+          Flow::Next => self.while_loop(condition, body)?,
+          flow => flow
+        },
+        Stmt::For { name, step, body, span, .. } => match self.resume(&body.statements, &path[1..])? {
+          Flow::Next => {
+            self.for_increment(name, *span, step.is_some());
+            self.for_loop(name, *span, step.is_some(), body)?
+          },
+          flow => flow
+        },
+        _ => unreachable!("labels are only nested inside IF, WHILE and FOR bodies")
+      };
+      if !matches!(flow, Flow::Next) {
+        return Ok(flow);
+      }
+    }
+    self.block(statements, index + 1)
+  }
+
+  fn while_loop(&mut self, condition: &Expr, body: &Block) -> Result<Flow, RuntimeError> {
+    while self.expression(condition)?.is_truthy() {
+      match self.enter(body)? {
+        Flow::Next => {},
+        flow => return Ok(flow)
+      }
+    }
+    Ok(Flow::Next)
+  }
+
+  // The step of the FOR loop at `span`: its hidden variable if it has a STEP, otherwise 1.
+  fn for_step(&self, span: Span, stepped: bool) -> Value {
+    if !stepped {
+      return Value::Int(1);
+    }
+    let (_, step_name) = ast::for_temporaries(span);
+    self.variables[&step_name].clone()
+  }
+
+  fn for_increment(&mut self, name: &str, span: Span, stepped: bool) {
+    let value = Value::binary(BinaryOp::Add, self.variables[name].clone(), self.for_step(span, stepped));
+    self.assign(name, value.expect("only division can fail"));
+  }
+
+  // Run a FOR loop whose variable, limit and step are already set, starting with the test.
+  fn for_loop(&mut self, name: &str, span: Span, stepped: bool, body: &Block) -> Result<Flow, RuntimeError> {
+    let (end_name, _) = ast::for_temporaries(span);
+    let ascending = Value::compare(CompareOp::GtEq, self.for_step(span, stepped), Value::Int(0)).is_truthy();
+    let test = if ascending { CompareOp::LtEq } else { CompareOp::GtEq };
+    loop {
+      let more = Value::compare(test, self.variables[name].clone(), self.variables[&end_name].clone());
+      if !more.is_truthy() {
+        return Ok(Flow::Next);
+      }
+      match self.enter(body)? {
+        Flow::Next => {},
+        flow => return Ok(flow)
+      }
+      self.for_increment(name, span, stepped);
+    }
+  }
+
+  fn statement(&mut self, statement: &Stmt) -> Result<Flow, RuntimeError> {
+    match statement {
+      Stmt::Print { value: PrintArg::Text(text), span } => {
+        writeln!(self.output, "{}", text).map_err(|err| io_error(*span, err))?;
+      },
+      Stmt::Print { value: PrintArg::Expr(expr), span } => {
+        let value = self.expression(expr)?;
+        writeln!(self.output, "{}", value::format_value(&value)).map_err(|err| io_error(*span, err))?;
+      },
+      Stmt::If { branches, else_body, .. } => {
+        for branch in branches.iter() {
+          if self.expression(&branch.condition)?.is_truthy() {
+            return self.enter(&branch.body);
+          }
+        }
+        if let Some(else_body) = else_body {
+          return self.enter(else_body);
+        }
+      },
+      Stmt::While { condition, body, .. } => return self.while_loop(condition, body),
+      Stmt::For { name, start, end, step, body, span } => {
+        // Same order as the C: the variable is assigned before the limit and step are evaluated.
+        let (end_name, step_name) = ast::for_temporaries(*span);
+        let start = self.expression(start)?;
+        self.assign(name, start);
+        let end = self.expression(end)?;
+        self.assign(&end_name, end);
+        if let Some(step) = step {
+          let step = self.expression(step)?;
+          self.assign(&step_name, step);
+        }
+        return self.for_loop(name, *span, step.is_some(), body);
+      },
+      Stmt::Label { .. } => {},
+      Stmt::Goto { name, .. } => return Ok(Flow::Goto(name.clone())),
+      Stmt::Let { name, value, .. } => {
+        let value = self.expression(value)?;
+        self.assign(name, value);
+      },
+      Stmt::Input { name, span, .. } => {
+        self.output.flush().map_err(|err| io_error(*span, err))?;
+        let ty = self.variables[name].ty();
+        // At end of input scanf leaves the variable as it was.
+        if let Some(value) = value::scan(&mut self.input, ty).map_err(|err| io_error(*span, err))? {
+          self.variables.insert(name.clone(), value);
+        }
+      },
+      Stmt::Dim { name, size, ty, .. } => {
+        let span = size.span();
+        let size = self.expression(size)?;
+        let array = Value::dim(*ty, size).map_err(|message| RuntimeError { span, message })?;
+        self.variables.insert(name.clone(), array);
+      },
+      Stmt::LetIndex { name, index, value, .. } => {
+        let span = index.span();
+        let index = self.expression(index)?;
+        let value = self.expression(value)?;
+        let array = self.variables.get_mut(name).unwrap();
+        array.set_element(name, index, value).map_err(|message| RuntimeError { span, message })?;
+      },
+      Stmt::Return { value, .. } => return Ok(Flow::Return(self.expression(value)?))
+    }
+    Ok(Flow::Next)
+  }
+
+  fn expression(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+    match expr {
+      Expr::Number { text, .. } => Ok(Value::from_literal(text)),
+      Expr::Bool { value, .. } => Ok(Value::Bool(*value)),
+      Expr::Text { text, .. } => Ok(Value::Str(Rc::from(text.as_str()))),
+      Expr::Ident { name, .. } => Ok(self.variables[name].clone()),
+      Expr::Index { name, index, .. } => {
+        let span = index.span();
+        let index = self.expression(index)?;
+        self.variables[name].element(name, index).map_err(|message| RuntimeError { span, message })
+      },
+      Expr::Unary { op, operand, .. } => Ok(Value::unary(*op, self.expression(operand)?)),
+      Expr::Binary { op, lhs, rhs, span } => {
+        let lhs = self.expression(lhs)?;
+        let rhs = self.expression(rhs)?;
+        Value::binary(*op, lhs, rhs).ok_or_else(|| RuntimeError {
+          span: *span,
+          message: String::from("integer division by zero")
+        })
+      },
+      Expr::Compare { op, lhs, rhs, .. } => {
+        let lhs = self.expression(lhs)?;
+        let rhs = self.expression(rhs)?;
+        Ok(Value::compare(*op, lhs, rhs))
+      },
+      Expr::Logical { op, lhs, rhs, .. } => {
+        let lhs = self.expression(lhs)?.is_truthy();
+        let result = match op {
+          LogicalOp::And => lhs && self.expression(rhs)?.is_truthy(),
+          LogicalOp::Or => lhs || self.expression(rhs)?.is_truthy()
+        };
+        Ok(Value::Bool(result))
+      },
+      Expr::Not { operand, .. } => Ok(Value::Bool(!self.expression(operand)?.is_truthy())),
+      Expr::Len { operand, .. } => Ok(Value::length(self.expression(operand)?)),
+      Expr::Call { name, args, span } => self.call(name, args, *span)
+    }
+  }
+}
+
+fn labels(body: &Block) -> Labels {
+  let mut labels = HashMap::new();
+  collect_labels(&body.statements, &mut Vec::new(), &mut labels);
+  labels
+}
+
+fn collect_labels(statements: &[Stmt], path: &mut Vec<(usize, usize)>, labels: &mut Labels) {
+  for (index, statement) in statements.iter().enumerate() {
+    if let Stmt::Label { name, .. } = statement {
+      let mut label_path = path.clone();
+      label_path.push((index, 0));
+      labels.insert(name.clone(), label_path);
+    }
+    for (body_index, body) in statement.bodies().into_iter().enumerate() {
+      path.push((index, body_index));
+      collect_labels(&body.statements, path, labels);
+      path.pop();
+    }
+  }
+}
+
+// Roughly where the top of the Rust stack is.
+fn stack_address() -> usize {
+  let marker = 0u8;
+  std::hint::black_box(&marker) as *const u8 as usize
+}
+
+fn io_error(span: Span, err: std::io::Error) -> RuntimeError {
+  RuntimeError { span, message: format!("I/O error: {}", err) }
+}
@@ -1,80 +1,148 @@
-use crate::token::Token;
+use std::borrow::Cow;
+
+use crate::error::{CompileError, Diagnostics};
+use crate::token::{Span, Token};
 use crate::enums::TokenKind;
 
 use core::fmt;
 
-#[derive(Debug, Clone)]
-pub struct LexingError;
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexingErrorKind {
+    UnknownCharacter(char),
+    BareBang,
+    UnterminatedString,
+    IllegalStringCharacter(char),
+    // The whole sequence, from the backslash on.
+    InvalidEscape(String),
+    MalformedNumber(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexingError {
+    pub kind: LexingErrorKind,
+    pub span: Span,
+}
 
 impl fmt::Display for LexingError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "invalid lexing token")
+        match &self.kind {
+            LexingErrorKind::UnknownCharacter(c) => write!(f, "unknown character {:?}", c),
+            LexingErrorKind::BareBang => write!(f, "expected '=' after '!'"),
+            LexingErrorKind::UnterminatedString => write!(f, "unterminated string literal"),
+            LexingErrorKind::IllegalStringCharacter(c) => write!(f, "illegal character {:?} in string literal", c),
+            LexingErrorKind::InvalidEscape(text) => write!(f, "invalid escape sequence '{}' in string literal", text),
+            LexingErrorKind::MalformedNumber(text) => write!(f, "malformed number {:?}: expected digits after '.'", text),
+        }
     }
 }
 
 
-pub struct Lexer {
-    pub source: String,
-    pub cur_char: char,
-    pub cur_pos: usize,
-    pub at_start: bool,
+/// Splits source text into tokens in a single pass. `cur_pos` is a byte offset into `source`,
+/// and token text borrows from it except where a string's escapes had to be decoded.
+pub struct Lexer<'a> {
+    source: &'a str,
+    pub(crate) file_name: String,
+    cur_char: char,
+    cur_pos: usize,
+    cur_line: usize,
+    cur_col: usize,
+    at_start: bool,
+    // Whether the NEWLINE that ends the last line has been produced. It comes just before EOF
+    // even when the source doesn't end with a newline.
+    at_end: bool,
+    // Whether EOF has been produced, which ends the iterator.
+    done: bool,
 }
 
-impl Default for Lexer {
+impl Default for Lexer<'_> {
     fn default() -> Self {
-        Self { source: Default::default(), cur_char: Default::default(), cur_pos: 0, at_start: true }
+        Self {
+            source: "",
+            file_name: String::from("<input>"),
+            cur_char: Default::default(),
+            cur_pos: 0,
+            cur_line: 1,
+            cur_col: 1,
+            at_start: true,
+            at_end: false,
+            done: false
+        }
     }
 }
 
-impl Lexer {
-    pub fn build(source: String) -> Lexer {
-        let source_plus_newline = format!("{}\n", source);
+impl<'a> Lexer<'a> {
+    pub fn build(source: &'a str) -> Lexer<'a> {
         let mut lexer = Lexer {
-            source: source_plus_newline,
+            source,
             ..Default::default()
         };
         lexer.next_char();
         lexer
     }
 
-    pub fn next_char(&mut self) {
+    pub fn build_for_file(file_name: String, source: &'a str) -> Lexer<'a> {
+        let mut lexer = Lexer::build(source);
+        lexer.file_name = file_name;
+        lexer
+    }
+
+    // Move to the next character, stepping over the whole UTF-8 encoding of the current one.
+    // Past the end of the source the current character is '\0'.
+    fn next_char(&mut self) {
         if !self.at_start {
-            self.cur_pos += 1;
-        }
-        if self.cur_pos >= self.source.chars().count() {
-            self.cur_char = '\0';
-        } else {
-            self.cur_char = self.source[self.cur_pos..self.cur_pos+1].chars().next().unwrap();
+            if self.cur_char == '\n' {
+                self.cur_line += 1;
+                self.cur_col = 1;
+            } else {
+                self.cur_col += 1;
+            }
+            self.cur_pos = self.end();
         }
+        self.cur_char = self.source[self.cur_pos..].chars().next().unwrap_or('\0');
         if self.at_start {
             self.at_start = false;
         }
+    }
+
+    /// The character at the current position, or `'\0'` at the end of the source.
+    #[cfg(test)]
+    pub fn cur_char(&self) -> char {
+        self.cur_char
+    }
 
+    pub fn peek(&self) -> char {
+        self.source[self.end()..].chars().next().unwrap_or('\0')
     }
 
-    pub fn peek(& self) -> char {
-        if self.cur_pos + 1 >= self.source.chars().count() {
-            return '\0';
+    // The byte offset just past the current character.
+    fn end(&self) -> usize {
+        (self.cur_pos + self.cur_char.len_utf8()).min(self.source.len())
+    }
+
+    fn error(&self, kind: LexingErrorKind, offset: usize, line: usize, col: usize) -> LexingError {
+        LexingError {
+            kind,
+            span: Span { offset, line, col, len: self.end() - offset }
         }
-        
-        self.source[self.cur_pos + 1..self.cur_pos + 2].chars().next().unwrap()
     }
 
-    pub fn skip_whitespace(&mut self) {
+    fn skip_whitespace(&mut self) {
         while self.cur_char == ' ' || self.cur_char == '\t' || self.cur_char == '\r' {
             self.next_char();
         }
     }
 
-    pub fn get_token(&mut self) -> Result<Token, LexingError> {
+    pub fn get_token(&mut self) -> Result<Token<'a>, LexingError> {
         self.skip_whitespace();
         self.skip_comment();
-        
-        let mut token: Token = Token::default();
-        
-        match self.cur_char.is_ascii_digit() {
-            true => {
-                let start_pos = self.cur_pos;
+
+        let start_pos = self.cur_pos;
+        let start_line = self.cur_line;
+        let start_col = self.cur_col;
+        let mut text = None;
+
+        let kind = match self.cur_char {
+            c if c.is_ascii_digit() => {
                 while self.peek().is_ascii_digit() {
                     self.next_char();
                 }
@@ -82,193 +150,205 @@ impl Lexer {
                     self.next_char();
 
                     if !self.peek().is_ascii_digit() {
-                        return Err(LexingError);
+                        let text = String::from(&self.source[start_pos..self.end()]);
+                        let err = self.error(LexingErrorKind::MalformedNumber(text), start_pos, start_line, start_col);
+                        self.next_char();
+                        return Err(err);
                     }
 
                     while self.peek().is_ascii_digit() {
                         self.next_char();
                     }
                 }
-
-                let mut token_text = String::new();
-                self.source[start_pos..self.cur_pos + 1].clone_into(&mut token_text);
-                token = Token {
-                    text: token_text,
-                    kind: TokenKind::NUMBER
+                TokenKind::NUMBER
+            },
+            c if c.is_alphabetic() => {
+                while self.peek().is_alphanumeric() {
+                    self.next_char();
                 }
+                Token::check_if_keyword(&self.source[start_pos..self.end()]).unwrap_or(TokenKind::IDENT)
+            },
+            '+' => TokenKind::PLUS,
+            '-' => TokenKind::MINUS,
+            '*' => TokenKind::ASTERISK,
+            '/' => TokenKind::SLASH,
+            '(' => TokenKind::LPAREN,
+            ')' => TokenKind::RPAREN,
+            ',' => TokenKind::COMMA,
+            ':' => TokenKind::COLON,
+            '=' => self.two_char('=', TokenKind::EQEQ, TokenKind::EQ),
+            '>' => self.two_char('=', TokenKind::GTEQ, TokenKind::GT),
+            '<' => self.two_char('=', TokenKind::LTEQ, TokenKind::LT),
+            '!' if self.peek() == '=' => {
+                self.next_char();
+                TokenKind::NOTEQ
+            },
+            '!' => {
+                let err = self.error(LexingErrorKind::BareBang, start_pos, start_line, start_col);
+                self.next_char();
+                return Err(err);
+            },
+            '"' => {
+                text = Some(self.string(start_pos, start_line, start_col)?);
+                TokenKind::STRING
+            },
+            '\n' => TokenKind::NEWLINE,
+            '\0' if !self.at_end => {
+                self.at_end = true;
+                let span = Span { offset: start_pos, line: start_line, col: start_col, len: 0 };
+                // EOF then comes at the start of the line after it, as if the newline were there.
+                self.cur_line += 1;
+                self.cur_col = 1;
+                return Ok(Token { text: Cow::Borrowed("\n"), kind: TokenKind::NEWLINE, span });
+            },
+            '\0' => {
+                let span = Span { offset: start_pos, line: start_line, col: start_col, len: 0 };
+                return Ok(Token { text: Cow::Borrowed(""), kind: TokenKind::EOF, span });
             },
             _ => {
-                match self.cur_char.is_alphabetic() {
-                    true => {
-                        let start_pos = self.cur_pos;
-                        while self.peek().is_alphanumeric() {
-                            self.next_char();
-                        }
-                        let mut text = String::new();
-                        self.source[start_pos..self.cur_pos + 1].clone_into(&mut text);
-                        let keyword = Token::check_if_keyword(&text);
-                        match keyword {
-                            Some(kind) => {
-                                token = Token {
-                                    text,
-                                    kind
-                                }
-                            },
-                            None => {
-                                token = Token {
-                                    text,
-                                    kind: TokenKind::IDENT
-                                }
-                            }
-                        }
-                        
-                    },
-                    _ => {
-                        match self.cur_char {
-                            '+' => {
-                                token = Token {
-                                    text: String::from(self.cur_char),
-                                    kind: TokenKind::PLUS
-                                }
-                            },
-                            '-' => {
-                                token = Token {
-                                    text: String::from(self.cur_char),
-                                    kind: TokenKind::MINUS
-                                }
-                            },
-                            '*' => {
-                                token = Token {
-                                    text: String::from(self.cur_char),
-                                    kind: TokenKind::ASTERISK
-                                }
-                            },
-                            '/' => {
-                                token = Token {
-                                    text: String::from(self.cur_char),
-                                    kind: TokenKind::SLASH
-                                }
-                            },
-                            '=' => {
-                                if self.peek() == '=' {
-                                    let previous_char = self.cur_char;
-                                    self.next_char();
-                                    let mut text = String::with_capacity(2);
-                                    text.insert(0, previous_char);
-                                    text.insert(1, self.cur_char);
-                                    token = Token {
-                                        text,
-                                        kind: TokenKind::EQEQ
-                                    }
-                                } else {
-                                    token = Token {
-                                        text: String::from(self.cur_char),
-                                        kind: TokenKind::EQ
-                                    }
-                                }
-                            },
-                            '>' => {
-                                if self.peek() == '=' {
-                                    let previous_char = self.cur_char;
-                                    self.next_char();
-                                    let mut text = String::with_capacity(2);
-                                    text.insert(0, previous_char);
-                                    text.insert(1, self.cur_char);
-                                    token = Token {
-                                        text,
-                                        kind: TokenKind::GTEQ
-                                    }
-                                } else {
-                                    token = Token  {
-                                        text: String::from(self.cur_char),
-                                        kind: TokenKind::GT
-                                    }
-                                }
-                            },
-                            '<' => {
-                                if self.peek() == '=' {
-                                    let previous_char = self.cur_char;
-                                    self.next_char();
-                                    let mut text = String::with_capacity(2);
-                                    text.insert(0, previous_char);
-                                    text.insert(1, self.cur_char);
-                                    token = Token {
-                                        text,
-                                        kind: TokenKind::LTEQ
-                                    }
-                                } else {
-                                    token = Token {
-                                        text: String::from(self.cur_char),
-                                        kind: TokenKind::LT
-                                    }
-                                }
-                            },
-                            '!' => {
-                                if self.peek() == '=' {
-                                    let previous_char = self.cur_char;
-                                    self.next_char();
-                                    let mut text = String::with_capacity(2);
-                                    text.insert(0, previous_char);
-                                    text.insert(1, self.cur_char);
-                                    token = Token {
-                                        text,
-                                        kind: TokenKind::NOTEQ
-                                    }
-                                } else {
-                                    return Err(LexingError);
-                                }
-                            },
-                            '\"' => {
-                                self.next_char();
-                                let start_pos = self.cur_pos;
-                
-                                while self.cur_char != '\"' {
-                                    if self.cur_char == '\r' || self.cur_char == '\n'
-                                        || self.cur_char == '\t' || self.cur_char == '\\'
-                                        || self.cur_char == '%' {
-                                            return Err(LexingError);
-                                        }
-                                    self.next_char();
-                                }
-                
-                                let mut token_text = String::new();
-                                self.source[start_pos..self.cur_pos].clone_into(&mut token_text);
-                                token = Token {
-                                    text: token_text,
-                                    kind: TokenKind::STRING
-                                }
-                            },
-                
-                            '\n' => {
-                                token = Token {
-                                    text: String::from(self.cur_char),
-                                    kind: TokenKind::NEWLINE
-                                }
-                            },
-                            '\0' => {
-                                token = Token {
-                                    text: String::from(self.cur_char),
-                                    kind: TokenKind::EOF
-                                }
-                            },
-                            _ => return Err(LexingError)
-                        }
-                    }
+                let err = self.error(LexingErrorKind::UnknownCharacter(self.cur_char), start_pos, start_line, start_col);
+                self.next_char();
+                return Err(err);
+            }
+        };
+
+        let end = self.end();
+        let token = Token {
+            text: text.unwrap_or(Cow::Borrowed(&self.source[start_pos..end])),
+            kind,
+            span: Span { offset: start_pos, line: start_line, col: start_col, len: end - start_pos }
+        };
+        self.next_char();
+        Ok(token)
+    }
+
+    // `one` if the next character is `second`, which then becomes part of the token, else `other`.
+    fn two_char(&mut self, second: char, one: TokenKind, other: TokenKind) -> TokenKind {
+        if self.peek() == second {
+            self.next_char();
+            one
+        } else {
+            other
+        }
+    }
+
+    // Lex a string literal from its opening quote, leaving the cursor on the closing one. The
+    // text is the string's contents, borrowed unless there were escapes to decode.
+    fn string(&mut self, start_pos: usize, start_line: usize, start_col: usize) -> Result<Cow<'a, str>, LexingError> {
+        self.next_char();
+        let text_start = self.cur_pos;
+        let mut decoded: Option<String> = None;
+        let mut illegal = None;
+
+        while self.cur_char != '"' {
+            if self.cur_char == '\n' || self.cur_char == '\0' {
+                // Leave the newline for the next token so the parser can resynchronize on it.
+                let mut err = self.error(LexingErrorKind::UnterminatedString, start_pos, start_line, start_col);
+                err.span.len = self.cur_pos - start_pos;
+                return Err(err);
+            }
+            if self.cur_char == '\\' && self.peek() != '\n' && self.peek() != '\0' {
+                let escape_start = self.cur_pos;
+                match self.escape() {
+                    Ok(c) => decoded.get_or_insert_with(|| String::from(&self.source[text_start..escape_start])).push(c),
+                    Err(err) => { illegal.get_or_insert(err); }
+                }
+            } else {
+                if illegal.is_none() && self.cur_char == '\r' {
+                    let mut err = self.error(LexingErrorKind::IllegalStringCharacter(self.cur_char),
+                        self.cur_pos, self.cur_line, self.cur_col);
+                    err.span.len = 1;
+                    illegal = Some(err);
+                }
+                if let Some(decoded) = &mut decoded {
+                    decoded.push(self.cur_char);
                 }
             }
+            self.next_char();
         }
 
+        if let Some(err) = illegal {
+            self.next_char();
+            return Err(err);
+        }
+        Ok(match decoded {
+            Some(decoded) => Cow::Owned(decoded),
+            None => Cow::Borrowed(&self.source[text_start..self.cur_pos])
+        })
+    }
 
+    // Decode the escape sequence starting at the backslash under the cursor: \n, \t, \", \\ or
+    // \u{...} with one to six hex digits. Leaves the cursor on its last character.
+    fn escape(&mut self) -> Result<char, LexingError> {
+        let (start_pos, start_line, start_col) = (self.cur_pos, self.cur_line, self.cur_col);
         self.next_char();
-        Ok(token)
+        let decoded = match self.cur_char {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            '"' => Some('"'),
+            '\\' => Some('\\'),
+            'u' if self.peek() == '{' => {
+                self.next_char();
+                while self.peek().is_ascii_hexdigit() {
+                    self.next_char();
+                }
+                let digits = &self.source[start_pos + 3..self.end()];
+                // A NUL would end the string early in C, so it can't be written either.
+                let code = (self.peek() == '}' && !digits.is_empty() && digits.len() <= 6)
+                    .then(|| u32::from_str_radix(digits, 16).ok().and_then(char::from_u32).filter(|&c| c != '\0'))
+                    .flatten();
+                if self.peek() == '}' {
+                    self.next_char();
+                }
+                code
+            },
+            _ => None
+        };
+        decoded.ok_or_else(|| {
+            let text = String::from(&self.source[start_pos..self.end()]);
+            self.error(LexingErrorKind::InvalidEscape(text), start_pos, start_line, start_col)
+        })
     }
 
-    pub fn skip_comment(&mut self) {
+    fn skip_comment(&mut self) {
         if self.cur_char == '#' {
-            while self.cur_char != '\n' {
+            while self.cur_char != '\n' && self.cur_char != '\0' {
                 self.next_char();
             }
         }
     }
 
-}
\ No newline at end of file
+}
+
+/// The tokens of a source text, ending with EOF, with each lexing error in the place it was found.
+/// The lexer moves past a bad lexeme, so the tokens after an error are still produced.
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>, LexingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let token = self.get_token();
+        self.done = matches!(&token, Ok(token) if token.kind == TokenKind::EOF);
+        Some(token)
+    }
+}
+
+/// All the tokens of `source`, ending with EOF, or every lexing error in it, each naming `file_name`.
+pub fn tokenize<'a>(source: &'a str, file_name: &str) -> Result<Vec<Token<'a>>, Diagnostics> {
+    let mut tokens = Vec::new();
+    let mut diagnostics = Diagnostics::default();
+    for token in Lexer::build(source) {
+        match token {
+            Ok(token) => tokens.push(token),
+            Err(error) => diagnostics.push(CompileError::Lexical { file: String::from(file_name), error })
+        }
+    }
+
+    if diagnostics.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(diagnostics)
+    }
+}
@@ -1,36 +1,454 @@
+pub mod ast;
+pub mod enums;
+pub mod error;
+pub mod token;
+
+// The rest of the compiler is internal; the CLI reaches it through `cli`.
+mod bytecode;
+mod checker;
+mod codegen;
+mod emitter;
+mod interpreter;
 mod lexer;
 mod parser;
-mod enums;
-mod token;
-mod emitter;
+mod toolchain;
+mod value;
+mod vm;
+
+#[cfg(test)]
+mod tests;
+
+pub use error::{CompileError, Diagnostics};
+pub use lexer::tokenize;
+pub use token::{Span, Token};
+use error::RuntimeError;
 
-use std::error::Error;
 use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::thread;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Mode {
+  // Translate the program into the format picked by --emit.
+  Compile,
+  // Execute the program directly without going through C.
+  Interpret,
+  // Compile the program to bytecode and execute it on the VM.
+  Vm,
+  // Compile the program to a native executable with the system C compiler.
+  Build,
+  Help,
+  Version
+}
+
+// What a compile produces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+  C,
+  Tokens,
+  // The tokens as a JSON array, for tools.
+  TokensJson,
+  Ast,
+  Bytecode
+}
 
-pub struct Config {
-  pub file_path: String,
+// Where the CLI writes what it produces.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Destination {
+  Stdout,
+  Path(String)
+}
+
+/// How `compile_str` compiles a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+  pub emit: Emit,
+  /// The name diagnostics, `#line` directives and bounds check errors use for the source.
+  pub file_name: String,
+  /// Put a `#line` directive before each statement of the C, so a C compiler reports problems
+  /// against the teeny source.
+  pub line_directives: bool,
+  /// Make the C check array sizes and indices at run time. Only applies to C.
+  pub bounds_checks: bool
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Self { emit: Emit::C, file_name: String::from("<input>"), line_directives: false, bounds_checks: false }
+  }
+}
+
+/// What `compile_str` produces, in the format `Options::emit` asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+  /// C, a token dump or a syntax tree dump.
+  Text(String),
+  /// Bytecode, as written to a `.ttbc` file.
+  Bytes(Vec<u8>)
+}
+
+impl Output {
+  pub fn as_bytes(&self) -> &[u8] {
+    match self {
+      Output::Text(text) => text.as_bytes(),
+      Output::Bytes(bytes) => bytes
+    }
+  }
+}
+
+pub(crate) struct Config {
+  pub(crate) file_path: String,
+  pub(crate) mode: Mode,
+  pub(crate) emit: Emit,
+  pub(crate) output: Destination,
+  // Build mode: flags passed through to the C compiler, and whether to run the executable.
+  pub(crate) cflags: Vec<String>,
+  pub(crate) run: bool,
+  // Whether the generated C checks array sizes and indices at run time.
+  pub(crate) bounds_checks: bool,
+}
+
+const USAGE: &str = "\
+Usage: teeny_tiny_compiler [OPTIONS] <FILE>
+
+Compiles a Teeny Tiny program to C, or runs it directly.
+
+Options:
+  -o <PATH>         Write output to PATH, or to stdout if PATH is -
+                    (default: FILE with the extension of the emitted format)
+  --emit <FORMAT>   What to produce: c (default), tokens, tokens-json, ast or bytecode
+  --interpret       Run the program with the tree-walking interpreter
+  --vm              Run the program on the bytecode VM; FILE may also be a .ttbc file
+  --build           Compile to a native executable with $CC (or cc, gcc, clang)
+                    (default output: FILE without its extension)
+  --run             Build, then run the executable
+  -O<LEVEL>         Optimization level passed to the C compiler when building
+  --bounds-checks   Make the generated C stop with an error on an array index out of bounds
+  -h, --help        Print this help
+  -V, --version     Print the version";
+
+impl Emit {
+  fn parse(name: &str) -> Result<Emit, String> {
+    match name {
+      "c" => Ok(Emit::C),
+      "tokens" => Ok(Emit::Tokens),
+      "tokens-json" => Ok(Emit::TokensJson),
+      "ast" => Ok(Emit::Ast),
+      "bytecode" => Ok(Emit::Bytecode),
+      _ => Err(format!("unknown --emit format '{}', expected c, tokens, tokens-json, ast or bytecode", name))
+    }
+  }
+
+  // File extension for the default output path.
+  pub fn extension(&self) -> &'static str {
+    match self {
+      Emit::C => "c",
+      Emit::Tokens => "tokens",
+      Emit::TokensJson => "json",
+      Emit::Ast => "ast",
+      Emit::Bytecode => "ttbc"
+    }
+  }
 }
 
 impl Config {
-  pub fn build(args: &[String]) -> Result<Config, &'static str> {
-    if args.len() < 2 {
-        return Err("no arguments provided");
+  pub(crate) fn build(args: &[String]) -> Result<Config, String> {
+    let mut file_path = None;
+    let mut mode = None;
+    let mut emit = None;
+    let mut output = None;
+    let mut cflags = Vec::new();
+    let mut run = false;
+    let mut bounds_checks = false;
+
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+      match arg.as_str() {
+        "-h" | "--help" => return Ok(Config::bare(Mode::Help)),
+        "-V" | "--version" => return Ok(Config::bare(Mode::Version)),
+        "--interpret" | "--vm" | "--build" | "--run" => {
+          let selected = match arg.as_str() {
+            "--interpret" => Mode::Interpret,
+            "--vm" => Mode::Vm,
+            _ => Mode::Build
+          };
+          if mode.is_some_and(|mode| mode != selected) {
+            return Err(String::from("choose only one of --interpret, --vm and --build"));
+          }
+          mode = Some(selected);
+          run |= arg == "--run";
+        },
+        "--bounds-checks" => bounds_checks = true,
+        "-O" | "-O0" | "-O1" | "-O2" | "-O3" | "-Os" | "-Oz" | "-Og" | "-Ofast" => cflags.push(arg.clone()),
+        "--emit" => {
+          let name = args.next().ok_or("--emit needs a format")?;
+          if emit.replace(Emit::parse(name)?).is_some() {
+            return Err(String::from("--emit given more than once"));
+          }
+        },
+        "-o" => {
+          let path = args.next().ok_or("-o needs a path")?;
+          let path = if path == "-" { Destination::Stdout } else { Destination::Path(path.clone()) };
+          if output.replace(path).is_some() {
+            return Err(String::from("-o given more than once"));
+          }
+        },
+        option if option.starts_with('-') => return Err(format!("unknown option '{}'", option)),
+        _ if file_path.is_some() => return Err(String::from("more than one source file provided")),
+        _ => file_path = Some(arg.clone())
+      }
+    }
+
+    let file_path = file_path.ok_or("no source file provided")?;
+    let mode = mode.unwrap_or(Mode::Compile);
+    if emit.is_some() && mode != Mode::Compile {
+      return Err(String::from("--emit only applies when compiling, not with --interpret, --vm or --build"));
+    }
+    if output.is_some() && (mode == Mode::Interpret || mode == Mode::Vm) {
+      return Err(String::from("-o doesn't apply with --interpret or --vm"));
+    }
+    if !cflags.is_empty() && mode != Mode::Build {
+      return Err(String::from("optimization flags only apply with --build or --run"));
     }
-    let file_path = args[1].clone();
+    if bounds_checks && (mode == Mode::Interpret || mode == Mode::Vm || emit.is_some_and(|emit| emit != Emit::C)) {
+      return Err(String::from("--bounds-checks only applies to C output; the interpreter and VM always check"));
+    }
+    if mode == Mode::Build && output == Some(Destination::Stdout) {
+      return Err(String::from("can't write an executable to stdout"));
+    }
+
+    let emit = emit.unwrap_or(Emit::C);
+    let output = match output {
+      Some(output) => output,
+      None => {
+        let extension = if mode == Mode::Build { "" } else { emit.extension() };
+        let derived = Path::new(&file_path).with_extension(extension);
+        if derived == Path::new(&file_path) {
+          return Err(format!("default output would overwrite {}; pass -o", file_path));
+        }
+        Destination::Path(derived.to_string_lossy().into_owned())
+      }
+    };
 
-    Ok(Config { file_path })
+    Ok(Config { file_path, mode, emit, output, cflags, run, bounds_checks })
   }
+
+  fn bare(mode: Mode) -> Config {
+    Config {
+      file_path: String::new(),
+      mode,
+      emit: Emit::C,
+      output: Destination::Stdout,
+      cflags: Vec::new(),
+      run: false,
+      bounds_checks: false
+    }
+  }
+
+  // How `compile_str` should compile for this configuration. Building adds `#line` directives so
+  // C compiler errors point into the teeny source.
+  fn options(&self) -> Options {
+    Options {
+      emit: self.emit,
+      file_name: self.file_path.clone(),
+      line_directives: self.mode == Mode::Build,
+      bounds_checks: self.bounds_checks
+    }
+  }
+
+  // Whether status messages would end up mixed into the program's or compiler's output.
+  pub(crate) fn is_quiet(&self) -> bool {
+    match self.mode {
+      Mode::Compile => self.output == Destination::Stdout,
+      Mode::Build => self.run,
+      _ => true
+    }
+  }
+}
+
+/// Run the compiler's command line on `args`, the first of which is the program's name, and
+/// return the process exit status: 0 on success, 64 for bad arguments, or else the
+/// `CompileError::exit_code` of the errors, which are printed to stderr.
+pub fn cli(args: &[String]) -> i32 {
+  let config = match Config::build(args) {
+    Ok(config) => config,
+    Err(err) => {
+      eprintln!("Problem parsing arguments: {err}");
+      eprintln!("Try '--help' for more information.");
+      return 64;
+    }
+  };
+
+  // Keep the program's or emitted output on stdout to itself.
+  if !config.is_quiet() {
+    println!("Teeny Tiny Compiler");
+  }
+
+  match run(config) {
+    Ok(()) => 0,
+    Err(diagnostics) => {
+      for e in diagnostics.iter() {
+        eprintln!("error: {e}");
+      }
+      if diagnostics.len() > 1 {
+        eprintln!("{} errors found", diagnostics.len());
+      }
+      diagnostics.exit_code()
+    }
+  }
+}
+
+pub(crate) fn run(config: Config) -> Result<(), Diagnostics> {
+  match config.mode {
+    Mode::Help => {
+      println!("{}", USAGE);
+      return Ok(());
+    },
+    Mode::Version => {
+      println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+      return Ok(());
+    },
+    _ => {}
+  }
+
+  // Precompiled bytecode can go straight to the VM.
+  if config.mode == Mode::Vm && config.file_path.ends_with(".ttbc") {
+    let bytes = fs::read(&config.file_path).map_err(|why| read_error(&config.file_path, why))?;
+    let chunk = bytecode::Chunk::from_bytes(&bytes).map_err(|err| {
+      CompileError::Io(io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", config.file_path, err)))
+    })?;
+    return run_vm(&config, &chunk);
+  }
+
+  let contents = fs::read_to_string(&config.file_path).map_err(|why| read_error(&config.file_path, why))?;
+  match config.mode {
+    Mode::Compile => {
+      let output = compile_str(&contents, &config.options())?;
+      write_output(&config, output.as_bytes())?;
+      if !config.is_quiet() {
+        println!("Compiling completed.");
+      }
+      Ok(())
+    },
+    Mode::Build => build(&config, &contents),
+    Mode::Interpret => interpret(&config, &parse_str(&contents, &config.file_path)?),
+    _ => run_vm(&config, &bytecode::Compiler::compile(&parse_str(&contents, &config.file_path)?))
+  }
+}
+
+/// Compile a program held in memory, without touching the filesystem. Every problem found in
+/// the source is returned, each naming `options.file_name`.
+pub fn compile_str(source: &str, options: &Options) -> Result<Output, Diagnostics> {
+  if options.emit == Emit::Tokens || options.emit == Emit::TokensJson {
+    let tokens = tokenize(source, &options.file_name)?;
+    return Ok(Output::Text(dump_tokens(&tokens, options.emit == Emit::TokensJson)));
+  }
+
+  let program = parse_str(source, &options.file_name)?;
+  Ok(match options.emit {
+    Emit::C => {
+      let mut emitter = emitter::Emitter::new();
+      let mut generator = codegen::CGenerator::new(&mut emitter);
+      if options.line_directives {
+        generator = generator.with_line_directives(&options.file_name);
+      }
+      if options.bounds_checks {
+        generator = generator.with_bounds_checks(&options.file_name);
+      }
+      generator.program(&program);
+      Output::Text(emitter.contents())
+    },
+    Emit::Ast => Output::Text(format!("{:#?}\n", program)),
+    Emit::Bytecode => Output::Bytes(bytecode::Compiler::compile(&program).to_bytes()),
+    Emit::Tokens | Emit::TokensJson => unreachable!("tokens are dumped before parsing")
+  })
+}
+
+/// Parse and check a program held in memory, for tools that want its syntax tree.
+pub fn parse_str(source: &str, file_name: &str) -> Result<ast::Program, Diagnostics> {
+  parser::Parser::new(lexer::Lexer::build_for_file(String::from(file_name), source)).program()
+}
+
+fn build(config: &Config, source: &str) -> Result<(), Diagnostics> {
+  let Destination::Path(path) = &config.output else {
+    unreachable!("Config::build rejects building to stdout");
+  };
+
+  let Output::Text(c) = compile_str(source, &config.options())? else {
+    unreachable!("building always emits C");
+  };
+  toolchain::build(&c, Path::new(path), &config.cflags)?;
+
+  if config.run {
+    toolchain::run(Path::new(path))?;
+  } else {
+    println!("successfully built {}", path);
+  }
+  Ok(())
+}
+
+// The interpreter recurses on the Rust stack, and its limit on nested calls fits in this much,
+// which the main thread doesn't have on every platform.
+const INTERPRETER_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+fn interpret(config: &Config, program: &ast::Program) -> Result<(), Diagnostics> {
+  let result = thread::scope(|scope| -> io::Result<Result<(), RuntimeError>> {
+    let handle = thread::Builder::new()
+      .stack_size(INTERPRETER_STACK_SIZE)
+      .spawn_scoped(scope, || {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        interpreter::Interpreter::new(stdin.lock(), stdout.lock()).run(program)
+      })?;
+    Ok(handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+  });
+  result.map_err(CompileError::from)?.map_err(|err| CompileError::runtime(&config.file_path, err))?;
+  Ok(())
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-  let contents = fs::read_to_string(config.file_path)?;
+fn run_vm(config: &Config, chunk: &bytecode::Chunk) -> Result<(), Diagnostics> {
+  let stdin = io::stdin();
+  let stdout = io::stdout();
+  let mut vm = vm::Vm::new(stdin.lock(), stdout.lock());
+  vm.run(chunk).map_err(|err| CompileError::runtime(&config.file_path, err))?;
+  Ok(())
+}
 
-  let lexer = lexer::Lexer::build(contents);
-  let emitter = emitter::Emitter::build(String::from("out.c"));
-  let mut parser = parser::Parser::new(lexer, emitter);
+fn read_error(file_path: &str, why: io::Error) -> CompileError {
+  CompileError::Io(io::Error::new(why.kind(), format!("couldn't read {}: {}", file_path, why)))
+}
 
-  parser.program();
-  println!("Compiling completed.");
+// One line per token: position, kind and text. With `json` the lines are objects in a JSON
+// array instead.
+fn dump_tokens(tokens: &[Token], json: bool) -> String {
+  let lines: Vec<String> = tokens.iter().map(|token| {
+    if json {
+      format!("  {}", token.to_json())
+    } else {
+      format!("{}\t{:?}\t{:?}", token.span, token.kind, token.text)
+    }
+  }).collect();
 
+  if json {
+    format!("[\n{}\n]\n", lines.join(",\n"))
+  } else {
+    lines.join("\n") + "\n"
+  }
+}
+
+fn write_output(config: &Config, bytes: &[u8]) -> Result<(), Diagnostics> {
+  match &config.output {
+    Destination::Stdout => {
+      let mut stdout = io::stdout().lock();
+      stdout.write_all(bytes).and_then(|_| stdout.flush()).map_err(CompileError::Io)?;
+    },
+    Destination::Path(path) => {
+      fs::write(path, bytes).map_err(|why| {
+        CompileError::Io(io::Error::new(why.kind(), format!("couldn't write to {}: {}", path, why)))
+      })?;
+      println!("successfully wrote to {}", path);
+    }
+  }
   Ok(())
 }
@@ -1,266 +1,853 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use crate::emitter::Emitter;
+use crate::ast::{self, BinaryOp, Block, Branch, CompareOp, Expr, Function, LogicalOp, PrintArg, Program, Stmt, Type, UnaryOp, Variable};
+use crate::checker;
+use crate::error::{CompileError, Diagnostics};
 use crate::lexer::Lexer;
-use crate::token::Token;
+use crate::token::{Span, Token};
 use crate::enums::TokenKind;
 
+// How deeply parentheses, subscripts, call arguments and NOTs may nest inside one another, a
+// little more than the 63 levels C promises. The parser and every stage after it recurse into
+// nested expressions, so this keeps them all well inside even a 2 MiB thread's stack.
+const MAX_NESTING: usize = 64;
+
+pub struct Parser<'a> {
+  cur_token: Option<Token<'a>>,
+  peek_token: Option<Token<'a>>,
+  lexer: Lexer<'a>,
+  // The blocks being parsed, innermost last. Function parameters sit in a scope of their own.
+  scopes: Vec<Scope>,
+  // The variables of every block in `scopes`, to look names up without going through each one.
+  visible: HashSet<String>,
+  // Variables whose block has already ended, to explain a later use of one.
+  out_of_scope: HashSet<String>,
+  // Whether each block, by scope id, has variables of its own.
+  block_has_locals: Vec<bool>,
+  // Each GOTO and LABEL with the ids of the scopes around it.
+  labels_gotoed: Vec<(String, Span, Vec<usize>)>,
+  labels_declared: HashMap<String, Vec<usize>>,
+  // Where each variable name is first assigned, in any function or the main program.
+  variables: HashMap<String, Span>,
+  // Each declared function's arity, and every call to check against them once all are known.
+  functions: HashMap<String, usize>,
+  calls: Vec<(String, usize, Span)>,
+  in_function: bool,
+  // How many nested expressions enclose the one being parsed.
+  nesting: usize,
+  diagnostics: Diagnostics
+}
 
-pub struct Parser {
-  cur_token: Option<Token>,
-  peek_token: Option<Token>,
-  lexer: Lexer,
-  emitter: Emitter,
-  symbols: HashSet<String>,
-  labels_gotoed: HashSet<String>,
-  labels_declared: HashSet<String>
+struct Scope {
+  id: usize,
+  // Variables first assigned directly in this block, in order.
+  variables: Vec<String>
 }
 
-impl Parser {
-  pub fn new(lexer: Lexer, emitter: Emitter) -> Parser {
+impl<'a> Parser<'a> {
+  pub fn new(lexer: Lexer<'a>) -> Parser<'a> {
 
     let mut parser = Parser {
       cur_token: None,
       peek_token: None,
       lexer,
-      emitter,
-      symbols: HashSet::new(),
-      labels_declared: HashSet::new(),
-      labels_gotoed: HashSet::new()
+      scopes: Vec::new(),
+      visible: HashSet::new(),
+      out_of_scope: HashSet::new(),
+      block_has_locals: Vec::new(),
+      labels_declared: HashMap::new(),
+      labels_gotoed: Vec::new(),
+      variables: HashMap::new(),
+      functions: HashMap::new(),
+      calls: Vec::new(),
+      in_function: false,
+      nesting: 0,
+      diagnostics: Diagnostics::default()
     };
 
 
-    parser.next_token();
-    parser.next_token();
+    parser.advance();
+    parser.advance();
     parser
   }
 
-  pub fn check_token(&mut self, kind: TokenKind) -> bool {
-    kind == self.cur_token.clone().unwrap_or(Token::default()).kind
+  fn check_token(&mut self, kind: TokenKind) -> bool {
+    kind == self.cur_kind()
   }
 
-  pub fn check_peek(&mut self, kind: TokenKind) -> bool {
-    kind == self.peek_token.clone().unwrap_or(Token::default()).kind
+  fn match_token(&mut self, kind: TokenKind) -> Result<(), CompileError> {
+    if !self.check_token(kind) {
+      let msg = format!("Expected {:?}, got {:?}", kind, self.cur_kind());
+      return Err(self.syntax_error(&msg));
+    }
+    Ok(())
   }
 
-  pub fn match_token(&mut self, kind: TokenKind) {
-    if !self.check_token(kind) {
-      let msg = format!("Expected {:?}, got {:?}", kind, self.cur_token.clone().unwrap_or(Token::default()).kind);
-      self.abort(&msg);
+  fn cur_kind(&self) -> TokenKind {
+    self.cur_token.as_ref().map_or(TokenKind::EOF, |token| token.kind)
+  }
+
+  fn next_token(&mut self) -> Result<(), CompileError> {
+    self.cur_token = self.peek_token.take();
+
+    // The lexer always moves past a bad lexeme, so keep going until we have a real
+    // token to look at. Only the first error is returned; any others are recorded.
+    let mut first_error = None;
+    while self.peek_token.is_none() {
+      match self.lexer.get_token() {
+        Ok(token) => self.peek_token = Some(token),
+        Err(error) => {
+          let err = CompileError::Lexical { file: self.lexer.file_name.clone(), error };
+          match first_error {
+            None => first_error = Some(err),
+            Some(_) => self.report(err)
+          }
+        }
+      }
+    }
+
+    match first_error {
+      None => Ok(()),
+      Some(err) => Err(err)
+    }
+  }
+
+  // Move to the next token, recording rather than returning any lexing error.
+  fn advance(&mut self) {
+    if let Err(err) = self.next_token() {
+      self.report(err);
+    }
+  }
+
+  fn report(&mut self, err: CompileError) {
+    // A syntax error later on the same line as a lexing error is almost always fallout
+    // from the bad token, so don't bury the real problem under it.
+    if let CompileError::Syntax { span, .. } = &err {
+      let follows_lexing_error = self.diagnostics.iter().any(|prev| match prev {
+        CompileError::Lexical { error, .. } => error.span.line == span.line && error.span.offset <= span.offset,
+        _ => false
+      });
+      if follows_lexing_error {
+        return;
+      }
     }
+    self.diagnostics.push(err);
   }
 
-  pub fn next_token(&mut self) {
-    self.cur_token = self.peek_token.clone();
-    self.peek_token = Some(self.lexer.get_token().unwrap_or(Token::default()));
+  // Skip the rest of a broken statement: up to and including the next NEWLINE,
+  // or up to (not including) one of the enclosing block's terminators.
+  fn synchronize(&mut self, terminators: &[TokenKind]) {
+    loop {
+      if self.check_token(TokenKind::NEWLINE) {
+        while self.check_token(TokenKind::NEWLINE) {
+          self.advance();
+        }
+        return;
+      }
+      if self.check_token(TokenKind::EOF) || terminators.iter().any(|&kind| self.check_token(kind)) {
+        return;
+      }
+      self.advance();
+    }
   }
 
-  pub fn abort(& self, msg: &str) {
-    panic!("{}", msg);
+  fn syntax_error(& self, msg: &str) -> CompileError {
+    CompileError::Syntax {
+      file: self.lexer.file_name.clone(),
+      span: self.cur_token.clone().unwrap_or_default().span,
+      message: String::from(msg)
+    }
   }
 
-  // program ::= {statement}
-  pub fn program(&mut self) {
-    self.emitter.header_line(String::from("#include <stdio.h>"));
-    self.emitter.header_line(String::from("int main(void){"));
+  // Parse something nested inside the current expression with `parse`, failing once expressions
+  // are nested more than `MAX_NESTING` deep.
+  fn nested(&mut self, parse: fn(&mut Self) -> Result<Expr, CompileError>) -> Result<Expr, CompileError> {
+    if self.nesting == MAX_NESTING {
+      let msg = format!("Expression is nested more than {} deep", MAX_NESTING);
+      return Err(self.syntax_error(&msg));
+    }
+    self.nesting += 1;
+    let expr = parse(self);
+    self.nesting -= 1;
+    expr
+  }
 
+  fn semantic_error(& self, span: Span, msg: &str) -> CompileError {
+    CompileError::Semantic {
+      file: self.lexer.file_name.clone(),
+      span,
+      message: String::from(msg)
+    }
+  }
+
+  // program ::= {function | statement}
+  pub fn program(&mut self) -> Result<Program, Diagnostics> {
     // Since some newlines are required in our grammar, need to skip the excess.
     while self.check_token(TokenKind::NEWLINE) {
-        self.next_token();
+        self.advance();
     }
 
-    // Parse all the statements in the program.
+    // Parse all the statements in the program, setting FUNCTION declarations aside.
+    let mut functions = Vec::new();
+    let mut statements = Vec::new();
+    self.push_scope();
     while !self.check_token(TokenKind::EOF) {
-      self.statement();
+      if self.check_token(TokenKind::FUNCTION) {
+        match self.function() {
+          Ok(function) => functions.push(function),
+          Err(err) => {
+            self.report(err);
+            self.synchronize(&[TokenKind::FUNCTION]);
+          }
+        }
+      } else {
+        statements.extend(self.statements(&[TokenKind::FUNCTION]));
+      }
+    }
+    let body = Block { statements, locals: self.pop_scope() };
+    self.check_gotos();
+
+    // Check each call against the declared functions.
+    for (name, args, span) in std::mem::take(&mut self.calls) {
+      match self.functions.get(&name) {
+        None => {
+          let msg = format!("Calling undeclared function: {}", name);
+          self.report(self.semantic_error(span, &msg));
+        },
+        Some(&params) if params != args => {
+          let plural = if params == 1 { "" } else { "s" };
+          let msg = format!("{} takes {} argument{} but was given {}", name, params, plural, args);
+          self.report(self.semantic_error(span, &msg));
+        },
+        Some(_) => {}
+      }
     }
 
-    // Wrap things up
-    self.emitter.emit_line(String::from("return 0;"));
-    self.emitter.emit_line(String::from("}"));
+    // In C a variable would hide the function of the same name.
+    for function in functions.iter() {
+      if let Some(&span) = self.variables.get(&function.name) {
+        let msg = format!("Variable has the same name as a FUNCTION: {}", function.name);
+        self.report(self.semantic_error(span, &msg));
+      }
+    }
+
+    // Types are only checked once the program is otherwise sound.
+    let mut program = Program { functions, body };
+    if self.diagnostics.is_empty() {
+      self.diagnostics = checker::check(&mut program, &self.lexer.file_name);
+    }
+
+    // Don't hand back a tree for a program we know is broken.
+    if !self.diagnostics.is_empty() {
+      let mut diagnostics = std::mem::take(&mut self.diagnostics);
+      diagnostics.sort();
+      return Err(diagnostics);
+    }
+
+    Ok(program)
+  }
 
-    // Check that each label referenced in a GOTO is declared.
-    for label in self.labels_gotoed.iter() {
-      if !self.labels_declared.contains(label) {
+  // Check that each label referenced in a GOTO is declared in the same function, and that the
+  // jump doesn't enter a block past the point where its variables are set to 0.
+  fn check_gotos(&mut self) {
+    for (label, span, goto_scopes) in std::mem::take(&mut self.labels_gotoed) {
+      let Some(label_scopes) = self.labels_declared.get(&label) else {
         let msg = format!("Attempting to GOTO to undeclared label: {}", label);
-        self.abort(&msg);
+        self.report(self.semantic_error(span, &msg));
+        continue;
+      };
+      let entered = label_scopes.iter().filter(|id| !goto_scopes.contains(id));
+      if entered.clone().any(|&id| self.block_has_locals[id]) {
+        let msg = format!("Attempting to GOTO into a block that has its own variables: {}", label);
+        self.report(self.semantic_error(span, &msg));
+      }
+    }
+  }
+
+  fn push_scope(&mut self) {
+    self.scopes.push(Scope { id: self.block_has_locals.len(), variables: Vec::new() });
+    self.block_has_locals.push(false);
+  }
+
+  // End the innermost block, returning its variables. Their types are filled in by the checker.
+  fn pop_scope(&mut self) -> Vec<Variable> {
+    let scope = self.scopes.pop().unwrap();
+    self.block_has_locals[scope.id] = !scope.variables.is_empty();
+    for variable in scope.variables.iter() {
+      self.visible.remove(variable);
+    }
+    self.out_of_scope.extend(scope.variables.iter().cloned());
+    scope.variables.into_iter().map(|name| Variable { name, ty: Type::Float, array: false }).collect()
+  }
+
+  fn is_visible(&self, name: &str) -> bool {
+    self.visible.contains(name)
+  }
+
+  // Give `name` to the innermost block unless it already names a visible variable.
+  fn declare_local(&mut self, name: &str) {
+    if self.visible.insert(String::from(name)) {
+      self.scopes.last_mut().unwrap().variables.push(String::from(name));
+    }
+  }
+
+  // function ::= "FUNCTION" ident "(" [ident {"," ident}] ")" nl {statement} "ENDFUNCTION" nl
+  fn function(&mut self) -> Result<Function, CompileError> {
+    let span = self.cur_token.clone().unwrap_or_default().span;
+    let (name, params, ret) = match self.function_header() {
+      Ok(header) => header,
+      Err(err) => {
+        // Still check the body; the placeholder never reaches a backend.
+        self.report(err);
+        self.synchronize(&[TokenKind::ENDFUNCTION]);
+        (String::new(), Vec::new(), Type::Float)
+      }
+    };
+
+    // The body has its own variables and labels; only the parameters are visible at the start.
+    let scopes = std::mem::take(&mut self.scopes);
+    let visible = std::mem::take(&mut self.visible);
+    let out_of_scope = std::mem::take(&mut self.out_of_scope);
+    let labels_declared = std::mem::take(&mut self.labels_declared);
+    let labels_gotoed = std::mem::take(&mut self.labels_gotoed);
+    self.in_function = true;
+    self.push_scope();
+    for param in params.iter() {
+      self.declare_local(&param.name);
+    }
+
+    let body = self.block(&[TokenKind::ENDFUNCTION]);
+    self.check_gotos();
+
+    self.in_function = false;
+    self.scopes = scopes;
+    self.visible = visible;
+    self.out_of_scope = out_of_scope;
+    self.labels_declared = labels_declared;
+    self.labels_gotoed = labels_gotoed;
+
+    self.match_token(TokenKind::ENDFUNCTION)?;
+    self.next_token()?;
+    self.nl()?;
+    Ok(Function { name, params, ret, body, span })
+  }
+
+  // Parse the `ident "(" [param {"," param}] ")" [":" type] nl` tail of a FUNCTION header, where
+  // param ::= ident [":" type]. Unannotated parameters and results are FLOAT.
+  fn function_header(&mut self) -> Result<(String, Vec<Variable>, Type), CompileError> {
+    self.next_token()?;
+    let name = self.cur_token.clone().unwrap();
+    self.match_token(TokenKind::IDENT)?;
+    if self.functions.contains_key(name.text.as_ref()) {
+      let msg = format!("Function already exists: {}", name.text);
+      self.report(self.semantic_error(name.span, &msg));
+    }
+    self.next_token()?;
+    self.match_token(TokenKind::LPAREN)?;
+    self.next_token()?;
+
+    let mut params = Vec::new();
+    if !self.check_token(TokenKind::RPAREN) {
+      loop {
+        let param = self.cur_token.clone().unwrap();
+        self.match_token(TokenKind::IDENT)?;
+        if params.iter().any(|other: &Variable| other.name == param.text) {
+          let msg = format!("Duplicate parameter: {}", param.text);
+          return Err(self.semantic_error(param.span, &msg));
+        }
+        self.variables.entry(param.text.to_string()).or_insert(param.span);
+        self.next_token()?;
+        let ty = self.annotation()?.unwrap_or(Type::Float);
+        params.push(Variable { name: param.text.into_owned(), ty, array: false });
+        if !self.check_token(TokenKind::COMMA) {
+          break;
+        }
+        self.next_token()?;
+      }
+    }
+    self.match_token(TokenKind::RPAREN)?;
+    self.next_token()?;
+    let ret = self.annotation()?.unwrap_or(Type::Float);
+    self.nl()?;
+
+    self.functions.insert(name.text.to_string(), params.len());
+    Ok((name.text.into_owned(), params, ret))
+  }
+
+  // annotation ::= [":" type], where type is INT, FLOAT, BOOL or STRING.
+  fn annotation(&mut self) -> Result<Option<Type>, CompileError> {
+    if !self.check_token(TokenKind::COLON) {
+      return Ok(None);
+    }
+    self.next_token()?;
+    let token = self.cur_token.clone().unwrap();
+    self.match_token(TokenKind::IDENT)?;
+    let Some(ty) = Type::from_name(&token.text) else {
+      let msg = format!("Unknown type {}; expected INT, FLOAT, BOOL or STRING", token.text);
+      return Err(self.syntax_error(&msg));
+    };
+    self.next_token()?;
+    Ok(Some(ty))
+  }
+
+  // Make the variable named by `token` visible to the statements that follow.
+  fn declare_variable(&mut self, token: &Token) {
+    if token.kind == TokenKind::IDENT {
+      self.declare_local(&token.text);
+      self.variables.entry(token.text.to_string()).or_insert(token.span);
+    }
+  }
+
+  // An error unless the variable named by `token` can be used here.
+  fn check_visible(&self, token: &Token) -> Result<(), CompileError> {
+    if self.is_visible(&token.text) {
+      return Ok(());
+    }
+    let msg = if self.out_of_scope.contains(token.text.as_ref()) {
+      format!("Referencing variable outside the block it was assigned in: {}", token.text)
+    } else {
+      format!("Referencing variable before assignment: {}", token.text)
+    };
+    Err(self.semantic_error(token.span, &msg))
+  }
+
+  // subscript ::= "(" expression ")"
+  fn subscript(&mut self) -> Result<Expr, CompileError> {
+    self.match_token(TokenKind::LPAREN)?;
+    self.next_token()?;
+    let expr = self.nested(Self::expression)?;
+    self.match_token(TokenKind::RPAREN)?;
+    self.next_token()?;
+    Ok(expr)
+  }
+
+  // block ::= {statement}, ending at EOF or one of `terminators`, with its own scope.
+  fn block(&mut self, terminators: &[TokenKind]) -> Block {
+    self.push_scope();
+    let statements = self.statements(terminators);
+    Block { statements, locals: self.pop_scope() }
+  }
+
+  // Parse statements up to EOF or one of `terminators`. A statement that fails to parse is
+  // recorded and skipped so the rest of the block is still checked.
+  fn statements(&mut self, terminators: &[TokenKind]) -> Vec<Stmt> {
+    let mut statements = Vec::new();
+    while !self.check_token(TokenKind::EOF) && !terminators.iter().any(|&kind| self.check_token(kind)) {
+      match self.statement() {
+        Ok(statement) => statements.push(statement),
+        Err(err) => {
+          self.report(err);
+          self.synchronize(terminators);
+        }
       }
     }
+    statements
+  }
 
-    self.emitter.write_file();
+  // Parse the `<comparison> THEN nl` tail of an IF or WHILE header, where `keyword` is THEN or REPEAT.
+  fn block_header(&mut self, keyword: TokenKind) -> Result<Expr, CompileError> {
+    let condition = self.expression()?;
+    self.match_token(keyword)?;
+    self.next_token()?;
+    self.nl()?;
+    Ok(condition)
   }
 
-  pub fn statement(&mut self) {
+  // Parse a block header, recording a failure and skipping to the body so it is still checked.
+  // The placeholder condition never reaches a backend since the error is already reported.
+  fn block_header_or_recover(&mut self, keyword: TokenKind, terminators: &[TokenKind]) -> Expr {
+    let span = self.cur_token.clone().unwrap_or_default().span;
+    match self.block_header(keyword) {
+      Ok(condition) => condition,
+      Err(err) => {
+        self.report(err);
+        self.synchronize(terminators);
+        Expr::Number { text: String::from("0"), span }
+      }
+    }
+  }
+
+  // Parse the `ident "=" expression "TO" expression ["STEP" expression] nl` tail of a FOR header.
+  fn for_header(&mut self) -> Result<(String, Expr, Expr, Option<Expr>), CompileError> {
+    let token = self.cur_token.clone().unwrap();
+    self.declare_variable(&token);
+    self.match_token(TokenKind::IDENT)?;
+    self.next_token()?;
+    self.match_token(TokenKind::EQ)?;
+    self.next_token()?;
+    let start = self.expression()?;
+    self.match_token(TokenKind::TO)?;
+    self.next_token()?;
+    let end = self.expression()?;
+    let mut step = None;
+    if self.check_token(TokenKind::STEP) {
+      self.next_token()?;
+      step = Some(self.expression()?);
+    }
+    self.nl()?;
+    Ok((token.text.into_owned(), start, end, step))
+  }
+
+  fn statement(&mut self) -> Result<Stmt, CompileError> {
+    let span = self.cur_token.clone().unwrap_or_default().span;
+    let statement;
+
     if self.check_token(TokenKind::PRINT) {
-      self.next_token();
+      self.next_token()?;
 
-      if self.check_token(TokenKind::STRING) {
-        self.emitter.emit_line(format!("printf(\"{}\\n\");", self.cur_token.clone().unwrap().text));
-        self.next_token();
+      // A lone string literal is printed as it is; one that starts a longer expression isn't.
+      if self.check_token(TokenKind::STRING)
+        && self.peek_token.as_ref().is_some_and(|peek| matches!(peek.kind, TokenKind::NEWLINE | TokenKind::EOF)) {
+        statement = Stmt::Print { value: PrintArg::Text(self.cur_token.as_ref().unwrap().text.to_string()), span };
+        self.next_token()?;
       } else {
         // Expect an expression.
-        self.emitter.emit(String::from("printf(\"%.2f\\n\", (float)("));
-        self.expression();
-        self.emitter.emit_line(String::from("));"));
+        statement = Stmt::Print { value: PrintArg::Expr(self.expression()?), span };
       }
     } else if self.check_token(TokenKind::IF) {
-      self.next_token();
-      self.emitter.emit(String::from("if("));
-      self.comparison();
-
-      self.match_token(TokenKind::THEN);
-      self.next_token();
-      self.nl();
-      self.emitter.emit_line(String::from("}{"));
-      while !self.check_token(TokenKind::ENDIF) {
-        self.statement();
+      // "IF" comparison "THEN" nl block {"ELSEIF" comparison "THEN" nl block} ["ELSE" nl block] "ENDIF"
+      const TERMINATORS: [TokenKind; 3] = [TokenKind::ENDIF, TokenKind::ELSEIF, TokenKind::ELSE];
+      let mut branches = Vec::new();
+      let mut else_body: Option<Block> = None;
+      let mut branch_span = span;
+
+      self.next_token()?;
+      loop {
+        let condition = self.block_header_or_recover(TokenKind::THEN, &TERMINATORS);
+        let body = self.block(&TERMINATORS);
+        branches.push(Branch { condition, body, span: branch_span });
+
+        // ELSE must come last; anything after it is still parsed so its errors are reported.
+        while self.check_token(TokenKind::ELSE) {
+          if else_body.is_some() {
+            self.report(self.syntax_error("IF already has an ELSE branch"));
+          }
+          self.next_token()?;
+          self.nl()?;
+          else_body = Some(self.block(&TERMINATORS));
+        }
+
+        if !self.check_token(TokenKind::ELSEIF) {
+          break;
+        }
+        branch_span = self.cur_token.clone().unwrap().span;
+        if else_body.is_some() {
+          self.report(self.syntax_error("ELSEIF must come before ELSE"));
+        }
+        self.next_token()?;
       }
-      self.match_token(TokenKind::ENDIF);
-      self.emitter.emit_line(String::from("}"));
-      self.next_token();
+
+      self.match_token(TokenKind::ENDIF)?;
+      self.next_token()?;
+      statement = Stmt::If { branches, else_body, span };
     } else if self.check_token(TokenKind::WHILE) {
-      self.next_token();
-      self.emitter.emit(String::from("while("));
-      self.comparison();
-
-      self.match_token(TokenKind::REPEAT);
-      self.next_token();
-      self.nl();
-      self.emitter.emit_line(String::from("){"));
-      while !self.check_token(TokenKind::ENDWHILE) {
-        self.statement();
+      self.next_token()?;
+      let condition = self.block_header_or_recover(TokenKind::REPEAT, &[TokenKind::ENDWHILE]);
+      let body = self.block(&[TokenKind::ENDWHILE]);
+      self.match_token(TokenKind::ENDWHILE)?;
+      self.next_token()?;
+      statement = Stmt::While { condition, body, span };
+    } else if self.check_token(TokenKind::FOR) {
+      // "FOR" ident "=" expression "TO" expression ["STEP" expression] nl block "NEXT"
+      self.next_token()?;
+      let (name, start, end, step) = match self.for_header() {
+        Ok(header) => header,
+        Err(err) => {
+          // As with IF and WHILE, still check the body; the placeholder never reaches a backend.
+          self.report(err);
+          self.synchronize(&[TokenKind::NEXT]);
+          let zero = Expr::Number { text: String::from("0"), span };
+          (String::new(), zero.clone(), zero, None)
+        }
+      };
+      let (end_name, step_name) = ast::for_temporaries(span);
+      self.declare_local(&end_name);
+      if step.is_some() {
+        self.declare_local(&step_name);
+      }
+      let body = self.block(&[TokenKind::NEXT]);
+      self.match_token(TokenKind::NEXT)?;
+      self.next_token()?;
+      statement = Stmt::For { name, start, end, step, body, span };
+    } else if self.check_token(TokenKind::RETURN) {
+      if !self.in_function {
+        return Err(self.syntax_error("RETURN is only allowed inside a FUNCTION"));
       }
-      self.match_token(TokenKind::ENDWHILE);
-      self.emitter.emit_line(String::from("}"));
-      self.next_token();
+      self.next_token()?;
+      statement = Stmt::Return { value: self.expression()?, span };
+    } else if self.check_token(TokenKind::FUNCTION) {
+      return Err(self.syntax_error("FUNCTION can only be declared at the top level"));
     } else if self.check_token(TokenKind::LABEL) {
-      println!("STATEMENT-LABEL");
-      self.next_token();
+      self.next_token()?;
 
-      if !self.labels_declared.contains(&self.cur_token.clone().unwrap().text) {
-        let msg = format!("Label already exists: {}", self.cur_token.clone().unwrap().text);
-        self.abort(&msg);
+      let label = self.cur_token.clone().unwrap();
+      self.match_token(TokenKind::IDENT)?;
+      if self.labels_declared.contains_key(label.text.as_ref()) {
+        let msg = format!("Label already exists: {}", label.text);
+        return Err(self.semantic_error(label.span, &msg));
       }
-      self.labels_declared.insert(self.cur_token.clone().unwrap().text);
-      self.emitter.emit_line(format!("{}:", self.cur_token.clone().unwrap().text));
-      self.match_token(TokenKind::IDENT);
-      self.next_token();
+      let scopes = self.scopes.iter().map(|scope| scope.id).collect();
+      self.labels_declared.insert(label.text.to_string(), scopes);
+      self.next_token()?;
+      statement = Stmt::Label { name: label.text.into_owned(), span };
     } else if self.check_token(TokenKind::GOTO) {
-      self.next_token();
-      self.labels_gotoed.insert(self.cur_token.clone().unwrap().text);
-      self.emitter.emit_line(format!("goto {};", self.cur_token.clone().unwrap().text));
-      self.match_token(TokenKind::IDENT);
-      self.next_token();
-
+      self.next_token()?;
+      let label = self.cur_token.clone().unwrap();
+      self.match_token(TokenKind::IDENT)?;
+      let scopes = self.scopes.iter().map(|scope| scope.id).collect();
+      self.labels_gotoed.push((label.text.to_string(), label.span, scopes));
+      self.next_token()?;
+      statement = Stmt::Goto { name: label.text.into_owned(), span };
+    } else if self.check_token(TokenKind::DIM) {
+      // "DIM" ident subscript [":" type]
+      self.next_token()?;
+      let token = self.cur_token.clone().unwrap();
+      self.match_token(TokenKind::IDENT)?;
+      self.next_token()?;
+      // The size is parsed first, as the array can't be used in it.
+      let size = self.subscript()?;
+      let ty = self.annotation()?.unwrap_or(Type::Float);
+      self.declare_variable(&token);
+      statement = Stmt::Dim { name: token.text.into_owned(), size, ty, span };
     } else if self.check_token(TokenKind::LET) {
-      self.next_token();
-      if !self.symbols.contains(&self.cur_token.as_ref().unwrap().text) {
-        self.symbols.insert(self.cur_token.clone().unwrap().text);
-        self.emitter.header_line(format!("float {};", self.cur_token.clone().unwrap().text));
+      self.next_token()?;
+      let token = self.cur_token.clone().unwrap();
+      if token.kind == TokenKind::IDENT && self.peek_token.as_ref().is_some_and(|peek| peek.kind == TokenKind::LPAREN) {
+        // "LET" ident subscript "=" expression
+        self.check_visible(&token)?;
+        self.next_token()?;
+        let index = self.subscript()?;
+        self.match_token(TokenKind::EQ)?;
+        self.next_token()?;
+        statement = Stmt::LetIndex { name: token.text.into_owned(), index, value: self.expression()?, span };
+      } else {
+        self.declare_variable(&token);
+        self.match_token(TokenKind::IDENT)?;
+        self.next_token()?;
+        let ty = self.annotation()?;
+        self.match_token(TokenKind::EQ)?;
+        self.next_token()?;
+        statement = Stmt::Let { name: token.text.into_owned(), ty, value: self.expression()?, span };
       }
-      self.emitter.emit(format!("{} = ", self.cur_token.clone().unwrap().text));
-      self.match_token(TokenKind::IDENT);
-      self.next_token();
-      self.match_token(TokenKind::EQ);
-      self.next_token();
-      self.expression();
-      self.emitter.emit_line(String::from(";"))
     } else if self.check_token(TokenKind::INPUT) {
-      self.next_token();
-      if !self.symbols.contains(&self.cur_token.as_ref().unwrap().text) {
-        self.symbols.insert(self.cur_token.clone().unwrap().text);
-        self.emitter.header_line(format!("float {};", self.cur_token.clone().unwrap().text));
-      }
-      self.emitter.emit_line(format!("if(0 == scanf(\"%f\", &{})) {{", self.cur_token.clone().unwrap().text));
-      self.emitter.emit_line(format!("{} = 0;", self.cur_token.clone().unwrap().text));
-      self.emitter.emit(String::from("scanf(\"&"));
-      self.emitter.emit_line(String::from("*s\");"));
-      self.emitter.emit_line(String::from("}"));
-      self.match_token(TokenKind::IDENT);
-      self.next_token();
+      self.next_token()?;
+      let token = self.cur_token.clone().unwrap();
+      self.declare_variable(&token);
+      self.match_token(TokenKind::IDENT)?;
+      self.next_token()?;
+      statement = Stmt::Input { name: token.text.into_owned(), ty: self.annotation()?, span };
     } else {
-      let msg = format!("Invalid statement at {} ({:?})", self.cur_token.clone().unwrap().text, self.cur_token.clone().unwrap().kind);
-      self.abort(&msg);
+      let msg = format!("Invalid statement at {} ({:?})", self.cur_token.clone().unwrap().describe(), self.cur_token.clone().unwrap().kind);
+      return Err(self.syntax_error(&msg));
     }
 
-    self.nl()
+    self.nl()?;
+    Ok(statement)
   }
   
-  pub fn nl(&mut self) {
+  fn nl(&mut self) -> Result<(), CompileError> {
     // Require at least one newline
-    self.match_token(TokenKind::NEWLINE);
+    self.match_token(TokenKind::NEWLINE)?;
     while self.check_token(TokenKind::NEWLINE) {
-      self.next_token();
+      self.next_token()?;
     }
+    Ok(())
   }
   
-  pub fn expression(&mut self) {
-    self.term();
+  // sum ::= term {( "-" | "+" ) term}
+  fn sum(&mut self) -> Result<Expr, CompileError> {
+    let mut expr = self.term()?;
 
     while self.check_token(TokenKind::PLUS) || self.check_token(TokenKind::MINUS) {
-      self.emitter.emit(format!("{}", self.cur_token.clone().unwrap().text));
-      self.next_token();
-      self.term();
+      let operator = self.cur_token.clone().unwrap();
+      self.next_token()?;
+      let rhs = self.term()?;
+      expr = Expr::Binary {
+        op: BinaryOp::from_token(operator.kind).unwrap(),
+        lhs: Box::new(expr),
+        rhs: Box::new(rhs),
+        span: operator.span
+      };
     }
+    Ok(expr)
   }
 
-  pub fn term(&mut self) {
-    self.unary();
+  // term ::= unary {( "/" | "*" ) unary}
+  fn term(&mut self) -> Result<Expr, CompileError> {
+    let mut expr = self.unary()?;
 
     while self.check_token(TokenKind::ASTERISK) || self.check_token(TokenKind::SLASH) {
-      self.emitter.emit(format!("{}", self.cur_token.clone().unwrap().text));
-      self.next_token();
-      self.unary();
+      let operator = self.cur_token.clone().unwrap();
+      self.next_token()?;
+      let rhs = self.unary()?;
+      expr = Expr::Binary {
+        op: BinaryOp::from_token(operator.kind).unwrap(),
+        lhs: Box::new(expr),
+        rhs: Box::new(rhs),
+        span: operator.span
+      };
     }
+    Ok(expr)
   }
 
-  pub fn unary(&mut self) {
+  // unary ::= ["+" | "-"] primary
+  fn unary(&mut self) -> Result<Expr, CompileError> {
     if self.check_token(TokenKind::PLUS) || self.check_token(TokenKind::MINUS) {
-      self.emitter.emit(format!("{}", self.cur_token.clone().unwrap().text));
-      self.next_token();
+      let operator = self.cur_token.clone().unwrap();
+      self.next_token()?;
+      let operand = self.primary()?;
+      return Ok(Expr::Unary {
+        op: UnaryOp::from_token(operator.kind).unwrap(),
+        operand: Box::new(operand),
+        span: operator.span
+      });
     }
 
-    self.primary();
+    self.primary()
   }
 
-  pub fn primary(&mut self) {
-    if self.check_token(TokenKind::NUMBER) {
-      self.emitter.emit(format!("{}", self.cur_token.clone().unwrap().text));
-      self.next_token();
-    } else if self.check_token(TokenKind::IDENT) {
-      if !self.symbols.contains(&self.cur_token.as_ref().unwrap().text) {
-        let msg = format!("Referencing variable before assignment: {}", self.cur_token.as_ref().unwrap().text);
-        self.abort(&msg)
+  // primary ::= number | "TRUE" | "FALSE" | string | ident | ident subscript | call | "LEN" subscript
+  //           | "(" expression ")"
+  fn primary(&mut self) -> Result<Expr, CompileError> {
+    let token = self.cur_token.clone().unwrap();
+    if self.check_token(TokenKind::IDENT) && self.peek_token.as_ref().is_some_and(|peek| peek.kind == TokenKind::LPAREN) {
+      // Variables and functions never share a name, so a variable's name here picks out an element.
+      if !self.is_visible(&token.text) && !self.out_of_scope.contains(token.text.as_ref()) {
+        return self.call();
       }
-      self.emitter.emit(format!("{}", self.cur_token.clone().unwrap().text));
-      self.next_token();
+      self.check_visible(&token)?;
+      self.next_token()?;
+      let index = self.subscript()?;
+      Ok(Expr::Index { name: token.text.into_owned(), index: Box::new(index), span: token.span })
+    } else if self.check_token(TokenKind::LEN) {
+      self.next_token()?;
+      let operand = self.subscript()?;
+      Ok(Expr::Len { operand: Box::new(operand), span: token.span })
+    } else if self.check_token(TokenKind::LPAREN) {
+      // Grouping only shapes the tree, so no node is kept for the parentheses themselves.
+      self.next_token()?;
+      let expr = self.nested(Self::expression)?;
+      self.match_token(TokenKind::RPAREN)?;
+      self.next_token()?;
+      Ok(expr)
+    } else if self.check_token(TokenKind::NUMBER) {
+      self.next_token()?;
+      Ok(Expr::Number { text: token.text.into_owned(), span: token.span })
+    } else if self.check_token(TokenKind::TRUE) || self.check_token(TokenKind::FALSE) {
+      self.next_token()?;
+      Ok(Expr::Bool { value: token.kind == TokenKind::TRUE, span: token.span })
+    } else if self.check_token(TokenKind::STRING) {
+      self.next_token()?;
+      Ok(Expr::Text { text: token.text.into_owned(), span: token.span })
+    } else if self.check_token(TokenKind::IDENT) {
+      self.check_visible(&token)?;
+      self.next_token()?;
+      Ok(Expr::Ident { name: token.text.into_owned(), span: token.span })
     } else {
-      let msg = format!("Unexpected token at {}", self.cur_token.clone().unwrap().text);
-      self.abort(&msg);
+      let msg = format!("Unexpected token at {}", token.describe());
+      Err(self.syntax_error(&msg))
     }
   }
 
-  pub fn is_comparison_operator(&mut self) -> bool { 
+  // call ::= ident "(" [expression {"," expression}] ")"
+  fn call(&mut self) -> Result<Expr, CompileError> {
+    let name = self.cur_token.clone().unwrap();
+    self.next_token()?;
+    self.next_token()?;
+
+    let mut args = Vec::new();
+    if !self.check_token(TokenKind::RPAREN) {
+      args.push(self.nested(Self::expression)?);
+      while self.check_token(TokenKind::COMMA) {
+        self.next_token()?;
+        args.push(self.nested(Self::expression)?);
+      }
+    }
+    self.match_token(TokenKind::RPAREN)?;
+    self.next_token()?;
+
+    // Functions may be declared after their callers, so arity is checked at the end.
+    self.calls.push((name.text.to_string(), args.len(), name.span));
+    Ok(Expr::Call { name: name.text.into_owned(), args, span: name.span })
+  }
+
+  fn is_comparison_operator(&mut self) -> bool { 
     self.check_token(TokenKind::GT) || self.check_token(TokenKind::GTEQ) 
       || self.check_token(TokenKind::LT) || self.check_token(TokenKind::LTEQ)
       || self.check_token(TokenKind::EQEQ) || self.check_token(TokenKind::NOTEQ)
   }
 
-  pub fn comparison(&mut self) {
-    self.expression();
+  // expression ::= and {"OR" and}
+  fn expression(&mut self) -> Result<Expr, CompileError> {
+    let mut expr = self.and()?;
+
+    while self.check_token(TokenKind::OR) {
+      let operator = self.cur_token.clone().unwrap();
+      self.next_token()?;
+      let rhs = self.and()?;
+      expr = Expr::Logical {
+        op: LogicalOp::Or,
+        lhs: Box::new(expr),
+        rhs: Box::new(rhs),
+        span: operator.span
+      };
+    }
+    Ok(expr)
+  }
 
-    if self.is_comparison_operator() {
-      self.emitter.emit(format!("{}", self.cur_token.clone().unwrap().text));
-      self.next_token();
-      self.expression();
-    } else {
-      let msg = format!("Expected comparison operator at: {}", self.cur_token.clone().unwrap().text);
-      self.abort(&msg);
+  // and ::= not {"AND" not}
+  fn and(&mut self) -> Result<Expr, CompileError> {
+    let mut expr = self.not()?;
+
+    while self.check_token(TokenKind::AND) {
+      let operator = self.cur_token.clone().unwrap();
+      self.next_token()?;
+      let rhs = self.not()?;
+      expr = Expr::Logical {
+        op: LogicalOp::And,
+        lhs: Box::new(expr),
+        rhs: Box::new(rhs),
+        span: operator.span
+      };
+    }
+    Ok(expr)
+  }
+
+  // not ::= "NOT" not | comparison
+  fn not(&mut self) -> Result<Expr, CompileError> {
+    if self.check_token(TokenKind::NOT) {
+      let operator = self.cur_token.clone().unwrap();
+      self.next_token()?;
+      let operand = self.nested(Self::not)?;
+      return Ok(Expr::Not { operand: Box::new(operand), span: operator.span });
+    }
+
+    self.comparison()
+  }
+
+  // comparison ::= sum [("==" | "!=" | ">" | ">=" | "<" | "<=") sum]
+  // Chains like `a < b < c` are rejected rather than compared as `(a < b) < c` the way C would.
+  fn comparison(&mut self) -> Result<Expr, CompileError> {
+    let lhs = self.sum()?;
+
+    if !self.is_comparison_operator() {
+      return Ok(lhs);
     }
 
-    while self.is_comparison_operator() {
-      self.emitter.emit(format!("{}", self.cur_token.clone().unwrap().text));
-      self.next_token();
-      self.expression();
+    let operator = self.cur_token.clone().unwrap();
+    self.next_token()?;
+    let rhs = self.sum()?;
+
+    if self.is_comparison_operator() {
+      let msg = format!("Comparisons can't be chained; combine them with AND or OR at: {}", self.cur_token.clone().unwrap().describe());
+      return Err(self.syntax_error(&msg));
     }
+
+    Ok(Expr::Compare {
+      op: CompareOp::from_token(operator.kind).unwrap(),
+      lhs: Box::new(lhs),
+      rhs: Box::new(rhs),
+      span: operator.span
+    })
   }
 }
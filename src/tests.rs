@@ -0,0 +1,665 @@
+use std::borrow::Cow;
+
+use crate::{Config, Destination, Emit, Mode, Options, Output};
+use crate::{lexer, token, enums, emitter, parser, error, ast, codegen, interpreter, bytecode, value, vm};
+
+#[test]
+fn test_peek() {
+    let source = String::from("LET foobar = 123 \n");
+    let lexer = lexer::Lexer::build(&source);
+    assert_eq!(lexer.peek(), 'E');
+}
+
+#[test]
+fn test_next() {
+    let source = String::from("LET foobar = 123");
+    let lexer = lexer::Lexer::build(&source);
+    assert_eq!(lexer.cur_char(), 'L');
+}
+
+#[test]
+fn test_token() {
+    let source = String::from("> */");
+    let mut lexer = lexer::Lexer::build(&source);
+
+    assert_eq!(lexer.get_token().unwrap_or_default().kind, enums::TokenKind::GT);
+}
+
+#[test]
+fn test_whitespace() {
+    let source = String::from(" - */");
+    let mut lexer = lexer::Lexer::build(&source);
+
+    assert_eq!(lexer.get_token().unwrap_or_default().kind, enums::TokenKind::MINUS);
+}
+
+#[test]
+fn test_comment() {
+    let source = String::from("# This is a comment!\n - */");
+    let mut lexer = lexer::Lexer::build(&source);
+
+    assert_eq!(lexer.get_token().unwrap_or_default().kind, enums::TokenKind::NEWLINE);
+}
+
+#[test]
+fn test_string() {
+    let source = String::from("\"This is Timothy\" - */");
+    let mut lexer = lexer::Lexer::build(&source);
+    assert_eq!(lexer.get_token().unwrap_or_default().kind, enums::TokenKind::STRING);
+}
+
+#[test]
+fn test_number() {
+    let source = String::from("1.90 - */");
+    let mut lexer = lexer::Lexer::build(&source);
+    assert_eq!(lexer.get_token().unwrap_or_default().kind, enums::TokenKind::NUMBER);
+}
+
+#[test]
+fn test_identifier() {
+    let source = String::from("foo 1.90");
+    let mut lexer = lexer::Lexer::build(&source);
+    assert_eq!(lexer.get_token().unwrap_or_default().kind, enums::TokenKind::IDENT);
+}
+
+#[test]
+fn test_keywords() {
+    let source = String::from("WHILE 1.90");
+    let mut lexer = lexer::Lexer::build(&source);
+    assert_eq!(lexer.get_token().unwrap_or_default().kind, enums::TokenKind::WHILE);
+}
+
+#[test]
+fn test_operators() {
+    let source = String::from("= +");
+    let mut lexer = lexer::Lexer::build(&source);
+    assert_eq!(lexer.get_token().unwrap_or_default().kind, enums::TokenKind::EQ);
+}
+
+#[test]
+fn test_span() {
+    let source = String::from("LET a = 1\n  PRINT \"hi\"");
+    let mut lexer = lexer::Lexer::build(&source);
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.get_token().unwrap_or_default();
+        if token.kind == enums::TokenKind::EOF {
+            break;
+        }
+        tokens.push(token);
+    }
+
+    let print = &tokens[5];
+    assert_eq!(print.kind, enums::TokenKind::PRINT);
+    assert_eq!(print.span, token::Span { offset: 12, line: 2, col: 3, len: 5 });
+    let string = &tokens[6];
+    assert_eq!(string.span, token::Span { offset: 18, line: 2, col: 9, len: 4 });
+}
+
+#[test]
+fn test_tokenize() {
+    let tokens = lexer::tokenize("LET a = \"x\"", "a.teeny").unwrap();
+    let kinds: Vec<enums::TokenKind> = tokens.iter().map(|token| token.kind).collect();
+    assert_eq!(kinds, [
+        enums::TokenKind::LET, enums::TokenKind::IDENT, enums::TokenKind::EQ, enums::TokenKind::STRING,
+        enums::TokenKind::NEWLINE, enums::TokenKind::EOF
+    ]);
+    assert_eq!(tokens[3].to_json(), r#"{"kind": "STRING", "text": "x", "line": 1, "col": 9, "offset": 8, "len": 3}"#);
+    let json = lexer::tokenize("\"say \\\"hi\\\"\\t\\\\\\u{1}\"", "a.teeny").unwrap()[0].to_json();
+    assert_eq!(json, r#"{"kind": "STRING", "text": "say \"hi\"\t\\\u0001", "line": 1, "col": 1, "offset": 0, "len": 21}"#);
+
+    // The iterator keeps going past errors and stops after EOF.
+    let results: Vec<bool> = lexer::Lexer::build("1 @ 2 !").map(|token| token.is_ok()).collect();
+    assert_eq!(results, [true, false, true, false, true, true]);
+    let diagnostics = lexer::tokenize("1 @ 2 !", "a.teeny").unwrap_err();
+    let messages: Vec<String> = diagnostics.iter().map(|err| err.to_string()).collect();
+    assert_eq!(messages, ["a.teeny:1:3: lexical error: unknown character '@'", "a.teeny:1:7: lexical error: expected '=' after '!'"]);
+}
+
+#[test]
+fn test_syntax_error() {
+    let source = String::from("PRINT 1\nLET = 2");
+    let lexer = lexer::Lexer::build_for_file(String::from("bad.teeny"), &source);
+    let mut parser = parser::Parser::new(lexer);
+
+    let diagnostics = parser.program().unwrap_err();
+    let err = &diagnostics.errors[0];
+    assert!(matches!(err, error::CompileError::Syntax { span: token::Span { line: 2, col: 5, .. }, .. }));
+    assert_eq!(err.to_string(), "bad.teeny:2:5: syntax error: Expected IDENT, got EQ");
+}
+
+#[test]
+fn test_lexing_errors() {
+    let mut lexer = lexer::Lexer::build("1. @ ! \"a\\qb\" \"open");
+    let kinds: Vec<lexer::LexingErrorKind> = (0..5).map(|_| lexer.get_token().unwrap_err().kind).collect();
+    assert_eq!(kinds, vec![
+        lexer::LexingErrorKind::MalformedNumber(String::from("1.")),
+        lexer::LexingErrorKind::UnknownCharacter('@'),
+        lexer::LexingErrorKind::BareBang,
+        lexer::LexingErrorKind::InvalidEscape(String::from("\\q")),
+        lexer::LexingErrorKind::UnterminatedString,
+    ]);
+    assert_eq!(lexer.get_token().unwrap().kind, enums::TokenKind::NEWLINE);
+}
+
+#[test]
+fn test_lexing_error_surfaces() {
+    let source = String::from("PRINT 1\nPRINT \"50\\%\"\n");
+    let lexer = lexer::Lexer::build_for_file(String::from("bad.teeny"), &source);
+    let err = parser::Parser::new(lexer).program().unwrap_err();
+    assert_eq!(err.to_string(), "bad.teeny:2:10: lexical error: invalid escape sequence '\\%' in string literal");
+}
+
+#[test]
+fn test_string_escapes() {
+    let source = "PRINT \"50% \\\"off\\\"\\tnow\\\\\\n\\u{e9}t\\u{1F600}??!\"\n";
+    let mut lexer = lexer::Lexer::build(source);
+    assert!(matches!(lexer.get_token().unwrap().text, Cow::Borrowed("PRINT")));
+    let text = lexer.get_token().unwrap().text;
+    assert!(matches!(text, Cow::Owned(_)));
+    assert_eq!(text, "50% \"off\"\tnow\\\n\u{e9}t\u{1F600}??!");
+    assert!(matches!(lexer::Lexer::build("\"plain\"").get_token().unwrap().text, Cow::Borrowed("plain")));
+
+    let program = parser::Parser::new(lexer::Lexer::build(source)).program().unwrap();
+    let mut emitter = emitter::Emitter::new();
+    codegen::CGenerator::new(&mut emitter).program(&program);
+    assert!(emitter.contents().contains("\n  puts(\"50% \\\"off\\\"\\tnow\\\\\\n\u{e9}t\u{1F600}?\\?!\");\n"));
+
+    let mut lexer = lexer::Lexer::build("\"\\u{}\" \"\\u{110000}\" \"\\u{0}\" \"\\u{41 \" \"\\\n");
+    let kinds: Vec<lexer::LexingErrorKind> = (0..5).map(|_| lexer.get_token().unwrap_err().kind).collect();
+    assert_eq!(kinds, vec![
+        lexer::LexingErrorKind::InvalidEscape(String::from("\\u{}")),
+        lexer::LexingErrorKind::InvalidEscape(String::from("\\u{110000}")),
+        lexer::LexingErrorKind::InvalidEscape(String::from("\\u{0}")),
+        lexer::LexingErrorKind::InvalidEscape(String::from("\\u{41")),
+        lexer::LexingErrorKind::UnterminatedString,
+    ]);
+}
+
+#[test]
+fn test_unicode() {
+    let source = String::from("# ü\nPRINT \"é\" + größe @\n");
+    let mut lexer = lexer::Lexer::build(&source);
+    let spans: Vec<String> = (0..5).map(|_| {
+        let token = lexer.get_token().unwrap();
+        format!("{:?} {} {} {}", token.kind, token.text, token.span, token.span.len)
+    }).collect();
+    assert_eq!(spans, ["NEWLINE \n 1:4 1", "PRINT PRINT 2:1 5", "STRING é 2:7 4", "PLUS + 2:11 1", "IDENT größe 2:13 7"]);
+    let err = lexer.get_token().unwrap_err();
+    assert_eq!((err.kind, err.span.to_string()), (lexer::LexingErrorKind::UnknownCharacter('@'), String::from("2:19")));
+
+    let source = String::from("LET größe = 1\nLET long = größe\nFUNCTION main(x: INT): INT\nRETURN x\nENDFUNCTION\nPRINT main(long)\n");
+    let program = parser::Parser::new(lexer::Lexer::build(&source)).program().unwrap();
+    let mut emitter = emitter::Emitter::new();
+    codegen::CGenerator::new(&mut emitter).program(&program);
+    let c = emitter.contents();
+    assert!(c.contains("\nint f_main(int v_x){\n"));
+    assert!(c.contains("\n  v_long = v_gr_f6__df_e;\n  printf(\"%d\\n\", f_main(v_long));\n"));
+}
+
+#[test]
+fn test_error_recovery() {
+    let source = String::from("LET a = \nIF a > THEN\n  PRINT b\n  PRINT 1\nENDIF\nWHILE a < 1 REPEAT\n  PRINT @\nENDWHILE\nGOTO nowhere\n");
+    let lexer = lexer::Lexer::build_for_file(String::from("bad.teeny"), &source);
+    let diagnostics = parser::Parser::new(lexer).program().unwrap_err();
+
+    let lines: Vec<usize> = diagnostics.iter().map(|err| err.span().unwrap().line).collect();
+    assert_eq!(lines, vec![1, 2, 3, 7, 9]);
+}
+
+// Parse a program that should fail, returning each diagnostic as it is displayed.
+fn errors(source: &str) -> Vec<String> {
+    let lexer = lexer::Lexer::build_for_file(String::from("bad.teeny"), source);
+    let diagnostics = parser::Parser::new(lexer).program().unwrap_err();
+    diagnostics.iter().map(|err| err.to_string()).collect()
+}
+
+#[test]
+fn test_label_needs_a_name() {
+    let messages = errors("LABEL 1\nLABEL 1\nGOTO 2\n");
+    assert_eq!(messages, [
+        "bad.teeny:1:7: syntax error: Expected IDENT, got NUMBER",
+        "bad.teeny:2:7: syntax error: Expected IDENT, got NUMBER",
+        "bad.teeny:3:6: syntax error: Expected IDENT, got NUMBER",
+    ]);
+}
+
+#[test]
+fn test_nesting_limit() {
+    let source = format!("PRINT {}1{}\nPRINT {}TRUE\n", "(".repeat(64), ")".repeat(64), "NOT ".repeat(64));
+    assert_eq!(interpret(&source, ""), "1\ntrue\n");
+    let program = parser::Parser::new(lexer::Lexer::build(&source)).program().unwrap();
+    let mut emitter = emitter::Emitter::new();
+    codegen::CGenerator::new(&mut emitter).program(&program);
+    assert!(emitter.contents().contains("(!(!(!(!"));
+
+    let source = format!("PRINT {}1{}\nPRINT {}TRUE\n", "(".repeat(20_000), ")".repeat(20_000), "NOT ".repeat(100_000));
+    assert_eq!(errors(&source), [
+        "bad.teeny:1:72: syntax error: Expression is nested more than 64 deep",
+        "bad.teeny:2:267: syntax error: Expression is nested more than 64 deep",
+    ]);
+}
+
+#[test]
+fn test_else_must_be_last() {
+    let source = String::from("IF 1 > 0 THEN
+ELSE
+PRINT 1
+ELSEIF 1 > 2 THEN
+ELSE
+ENDIF
+ELSE
+");
+    let messages = errors(&source);
+    assert_eq!(messages[..2], [
+        "bad.teeny:4:1: syntax error: ELSEIF must come before ELSE",
+        "bad.teeny:5:1: syntax error: IF already has an ELSE branch",
+    ]);
+    assert!(messages.last().unwrap().starts_with("bad.teeny:7:"));
+}
+
+#[test]
+fn test_for_header_errors() {
+    let source = String::from("FOR i = 1 3
+  PRINT b
+NEXT
+FOR j = 1 TO 2
+  PRINT j
+NEXT
+PRINT j
+");
+    let messages = errors(&source);
+    assert_eq!(messages, [
+        "bad.teeny:1:11: syntax error: Expected TO, got NUMBER",
+        "bad.teeny:2:9: semantic error: Referencing variable before assignment: b",
+    ]);
+}
+
+#[test]
+fn test_chained_comparison() {
+    let source = String::from("LET a = 1\nIF 0 < a < 2 THEN\nENDIF\n");
+    let lexer = lexer::Lexer::build_for_file(String::from("bad.teeny"), &source);
+    let err = parser::Parser::new(lexer).program().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "bad.teeny:2:10: syntax error: Comparisons can't be chained; combine them with AND or OR at: <"
+    );
+}
+
+#[test]
+fn test_function_errors() {
+    let source = String::from("FUNCTION f(a, b)\n  RETURN a + c\nENDFUNCTION\nLET a = 1\nPRINT f(a)\nPRINT g(a)\n\
+        RETURN a\nIF a > 0 THEN\n  FUNCTION h()\nENDIF\nFUNCTION f(x, x)\nENDFUNCTION\nLET f = 2\n");
+    let messages = errors(&source);
+    assert_eq!(messages, [
+        "bad.teeny:2:14: semantic error: Referencing variable before assignment: c",
+        "bad.teeny:5:7: semantic error: f takes 2 arguments but was given 1",
+        "bad.teeny:6:7: semantic error: Calling undeclared function: g",
+        "bad.teeny:7:1: syntax error: RETURN is only allowed inside a FUNCTION",
+        "bad.teeny:9:3: syntax error: FUNCTION can only be declared at the top level",
+        "bad.teeny:11:10: semantic error: Function already exists: f",
+        "bad.teeny:11:15: semantic error: Duplicate parameter: x",
+        "bad.teeny:13:5: semantic error: Variable has the same name as a FUNCTION: f",
+    ]);
+}
+
+#[test]
+fn test_scope_errors() {
+    let source = String::from("LET a = 1\nIF a > 0 THEN\n  LET b = a\nENDIF\nPRINT b\nGOTO inner\n\
+        WHILE a < 3 REPEAT\n  LABEL inner\n  LET c = a\n  LET a = a + 1\nENDWHILE\n");
+    let messages = errors(&source);
+    assert_eq!(messages, [
+        "bad.teeny:5:7: semantic error: Referencing variable outside the block it was assigned in: b",
+        "bad.teeny:6:6: semantic error: Attempting to GOTO into a block that has its own variables: inner",
+    ]);
+}
+
+#[test]
+fn test_type_errors() {
+    let source = String::from("LET n = 1\nLET n = 2.5\nLET f: BOOL = n\nIF n THEN\nENDIF\n\
+        LET s = \"a\" - 1\nINPUT f\nFUNCTION g(k: INT): INT\n  RETURN k / 2.0\nENDFUNCTION\nPRINT g(1.5)\n\
+        LET t = t + 1\nPRINT LEN(n) + LEN(\"ab\")\nPRINT \"a\" < \"b\"\nLET u: CHAR = 1\n");
+    let messages = errors(&source);
+    assert_eq!(messages, ["bad.teeny:15:8: syntax error: Unknown type CHAR; expected INT, FLOAT, BOOL or STRING"]);
+
+    let source = source.replace("LET u: CHAR = 1\n", "");
+    let messages = errors(&source);
+    assert_eq!(messages, [
+        "bad.teeny:2:9: semantic error: Type mismatch: can't assign FLOAT to n, which is INT",
+        "bad.teeny:3:15: semantic error: Type mismatch: can't assign INT to f, which is BOOL",
+        "bad.teeny:4:4: semantic error: Type mismatch: condition must be BOOL, got INT",
+        "bad.teeny:6:13: semantic error: Type mismatch: can't apply - to STRING and INT",
+        "bad.teeny:7:1: semantic error: INPUT can only read numbers and strings, but f is BOOL",
+        "bad.teeny:9:12: semantic error: Type mismatch: g must return INT, got FLOAT",
+        "bad.teeny:11:9: semantic error: Type mismatch: argument 1 of g must be INT, got FLOAT",
+        "bad.teeny:12:9: semantic error: Can't infer the type of t from a value that uses it; annotate it, as in LET t: FLOAT",
+        "bad.teeny:13:7: semantic error: Type mismatch: LEN needs a STRING, got INT",
+        "bad.teeny:14:11: semantic error: Type mismatch: can't apply < to STRING and STRING",
+    ]);
+}
+
+#[test]
+fn test_array_errors() {
+    let source = String::from("DIM a(3)\nLET a(3) = 1\nLET a(-1) = 2\nLET a(1) = \"x\"\nPRINT a\nLET a = 1\nLET n = 2\n\
+        PRINT n(0)\nPRINT a(1.5)\nDIM b(2.0)\nDIM n(2)\nDIM a(4)\nDIM c(-2)\nFOR a = 1 TO 2\nNEXT\n");
+    let messages = errors(&source);
+    assert_eq!(messages, [
+        "bad.teeny:2:7: semantic error: Index 3 is out of bounds for a, whose size is 3",
+        "bad.teeny:3:7: semantic error: Index -1 is out of bounds for a, whose size is 3",
+        "bad.teeny:4:12: semantic error: Type mismatch: can't assign STRING to an element of a, which holds FLOAT",
+        "bad.teeny:5:7: semantic error: a is an array; use one element at a time, as in a(0)",
+        "bad.teeny:6:1: semantic error: a is an array; use one element at a time, as in a(0)",
+        "bad.teeny:8:7: semantic error: n is not an array; declare one with DIM n(size)",
+        "bad.teeny:9:9: semantic error: Type mismatch: array index must be INT, got FLOAT",
+        "bad.teeny:10:7: semantic error: Type mismatch: array size must be INT, got FLOAT",
+        "bad.teeny:11:1: semantic error: Can't DIM n, which is already a variable",
+        "bad.teeny:12:1: semantic error: Array a already has a DIM",
+        "bad.teeny:13:7: semantic error: Array size can't be negative: -2",
+        "bad.teeny:14:1: semantic error: a is an array; use one element at a time, as in a(0)",
+    ]);
+}
+
+#[test]
+fn test_ast() {
+    let source = String::from("LET a = 1 + 2 * -3\n");
+    let program = parser::Parser::new(lexer::Lexer::build(&source)).program().unwrap();
+    let ast::Stmt::Let { name, value, .. } = &program.body.statements[0] else {
+        panic!("expected LET");
+    };
+    assert_eq!(name, "a");
+    let ast::Expr::Binary { op: ast::BinaryOp::Add, rhs, .. } = value else {
+        panic!("expected addition at the root");
+    };
+    assert!(matches!(**rhs, ast::Expr::Binary { op: ast::BinaryOp::Mul, .. }));
+}
+
+#[test]
+fn test_codegen() {
+    let source = String::from("INPUT n\nWHILE n > 0 REPEAT\nPRINT n * 2\nLET n = n - 1\nENDWHILE\n");
+    let program = parser::Parser::new(lexer::Lexer::build(&source)).program().unwrap();
+    let mut emitter = emitter::Emitter::new();
+    codegen::CGenerator::new(&mut emitter).program(&program);
+    assert_eq!(emitter.contents(), "#include <stdbool.h>\n#include <stdio.h>\n\nint main(void){\n  double v_n = 0;\n\
+        \x20 if(0 == scanf(\"%lf\", &v_n)) {\n    v_n = 0;\n    scanf(\"%*s\");\n  }\n\
+        \x20 while(v_n>0){\n    printf(\"%.2f\\n\", v_n*2);\n    v_n = v_n-1;\n  }\n  return 0;\n}\n");
+}
+
+#[test]
+fn test_codegen_parenthesizes() {
+    let source = String::from("LET a = 1\nLET b = a - -(a + 2) * a\n");
+    let program = parser::Parser::new(lexer::Lexer::build(&source)).program().unwrap();
+    let mut emitter = emitter::Emitter::new();
+    codegen::CGenerator::new(&mut emitter).program(&program);
+    assert!(emitter.contents().contains("\n  v_b = v_a-((-(v_a+2))*v_a);\n"));
+}
+
+#[test]
+fn test_emitter() {
+    let mut emitter = emitter::Emitter::new();
+    emitter.emit_line("int main(void){");
+    emitter.indent();
+    emitter.emit("if(x");
+    emitter.emit(" > 1){\n");
+    emitter.indent();
+    emitter.emit_line("#line 3 \"a.teeny\"");
+    emitter.emit_line("x = 1;\nx = 2;");
+    emitter.dedent();
+    emitter.emit_line("}");
+    emitter.set_section(emitter::Section::Includes);
+    emitter.emit_line("#include <stdio.h>");
+    emitter.set_section(emitter::Section::Main);
+    emitter.dedent();
+    emitter.emit_line("}");
+
+    let expected = "#include <stdio.h>\n\nint main(void){\n  if(x > 1){\n#line 3 \"a.teeny\"\n    x = 1;\n    x = 2;\n  }\n}\n";
+    assert_eq!(emitter.contents(), expected);
+    let mut sink = Vec::new();
+    emitter.write_to(&mut sink).unwrap();
+    assert_eq!(sink, expected.as_bytes());
+    assert_eq!(emitter::Emitter::new().contents(), "");
+}
+
+// Run a program on both the interpreter and the VM, checking that they agree.
+fn interpret(source: &str, input: &str) -> String {
+    let program = parser::Parser::new(lexer::Lexer::build(source)).program().unwrap();
+    let mut output = Vec::new();
+    interpreter::Interpreter::new(input.as_bytes(), &mut output).run(&program).unwrap();
+
+    let chunk = bytecode::Compiler::compile(&program);
+    let mut vm_output = Vec::new();
+    vm::Vm::new(input.as_bytes(), &mut vm_output).run(&chunk).unwrap();
+    assert_eq!(output, vm_output);
+
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn test_interpreter() {
+    let source = "PRINT \"fib\"\nINPUT nums\nLET a = 0\nLET b = 1\nWHILE nums > 0 REPEAT\n\
+        PRINT a\nLET c = a + b\nLET a = b\nLET b = c\nLET nums = nums - 1\nENDWHILE\nPRINT 7 / 2\nPRINT 7.0 / 2\n";
+    assert_eq!(interpret(source, "5\n"), "fib\n0\n1\n1\n2\n3\n3\n3.50\n");
+}
+
+#[test]
+fn test_interpreter_goto() {
+    let source = "LET i = 0\nGOTO inside\nWHILE i < 3 REPEAT\nPRINT i\nLABEL inside\nLET i = i + 1\nENDWHILE\n\
+        INPUT x\nINPUT y\nPRINT x + y\nIF y == 0 THEN\nGOTO done\nENDIF\nPRINT 99\nLABEL done\n";
+    assert_eq!(interpret(source, "4 oops"), "1\n2\n4.00\n");
+}
+
+#[test]
+fn test_deep_recursion() {
+    let source = "FUNCTION sum(n: INT): INT\nIF n == 0 THEN\nRETURN 0\nENDIF\nRETURN n + sum(n - 1)\nENDFUNCTION\nPRINT sum(5000)\n";
+    let program = parser::Parser::new(lexer::Lexer::build(source)).program().unwrap();
+    let mut output = Vec::new();
+    vm::Vm::new("".as_bytes(), &mut output).run(&bytecode::Compiler::compile(&program)).unwrap();
+    assert_eq!(output, b"12502500\n");
+    assert_eq!(interpret(&source.replace("5000", "100"), ""), "5050\n");
+
+    // The interpreter stops on its own well within a normal thread's stack.
+    let result = std::thread::Builder::new().stack_size(8 * 1024 * 1024).spawn(move || {
+        interpreter::Interpreter::new("".as_bytes(), Vec::new()).run(&program).unwrap_err().message
+    });
+    assert_eq!(result.unwrap().join().unwrap(), "stack overflow calling sum");
+
+    let source = "FUNCTION forever(n: INT): INT\nRETURN forever(n + 1)\nENDFUNCTION\nPRINT forever(0)\n";
+    let program = parser::Parser::new(lexer::Lexer::build(source)).program().unwrap();
+    let err = vm::Vm::new("".as_bytes(), Vec::new()).run(&bytecode::Compiler::compile(&program)).unwrap_err();
+    assert_eq!(err.message, "stack overflow calling forever");
+}
+
+#[test]
+fn test_strings_are_owned() {
+    let source = "FUNCTION f(s: STRING): STRING\nRETURN s\nENDFUNCTION\nLET a = \"x\"\nLET b = a\nLET a = a + f(b)\nPRINT f(a) + b\n";
+    let program = parser::Parser::new(lexer::Lexer::build(source)).program().unwrap();
+    let mut emitter = emitter::Emitter::new();
+    codegen::CGenerator::new(&mut emitter).program(&program);
+    let c = emitter.contents();
+    assert!(c.contains("\n  {\n    char* tt_result = tt_copy(v_s);\n    tt_release(v_s);\n    return tt_result;\n  }\n  return tt_empty;\n"));
+    assert!(c.contains("\n  char* v_a = tt_empty;\n  char* v_b = tt_empty;\n  tt_assign(&v_a, tt_copy(\"x\"));\n  tt_assign(&v_b, tt_copy(v_a));\n"));
+    assert!(c.contains("\n  tt_assign(&v_a, tt_concat(v_a, false, f_f(tt_copy(v_b)), true));\n"));
+    assert!(c.contains("\n  tt_print(tt_concat(f_f(tt_copy(v_a)), true, v_b, false), true);\n  tt_release(v_a);\n  tt_release(v_b);\n  return 0;\n"));
+}
+
+#[test]
+fn test_arrays_are_freed() {
+    let source = "FUNCTION f(n: INT): INT\nDIM t(1): INT\nIF n > 0 THEN\nDIM u(1): INT\nRETURN u(0)\nENDIF\nRETURN n\nENDFUNCTION\n\
+        LABEL top\nWHILE f(1) > 0 REPEAT\nDIM a(2): INT\nGOTO top\nENDWHILE\n";
+    let program = parser::Parser::new(lexer::Lexer::build(source)).program().unwrap();
+    let mut emitter = emitter::Emitter::new();
+    codegen::CGenerator::new(&mut emitter).program(&program);
+    let c = emitter.contents();
+    assert!(c.contains("\n    {\n      int tt_result = v_u[0];\n      free(v_u);\n      free(v_t);\n      return tt_result;\n    }\n  }\n"));
+    assert!(c.contains("\n  {\n    int tt_result = v_n;\n    free(v_t);\n    return tt_result;\n  }\n  return 0;\n"));
+    assert!(c.contains("\n    free(v_a);\n    goto v_top;\n  }\n"));
+}
+
+#[test]
+fn test_array_bounds() {
+    let source = "INPUT n: INT\nDIM a(n): INT\nLET a(n - 1) = 5\nPRINT a(n - 1)\nPRINT a(n)\n";
+    let program = parser::Parser::new(lexer::Lexer::build(source)).program().unwrap();
+    let expected = "index 3 is out of bounds for a, whose size is 3";
+
+    let mut output = Vec::new();
+    let err = interpreter::Interpreter::new("3".as_bytes(), &mut output).run(&program).unwrap_err();
+    assert_eq!((err.span.line, err.span.col, err.message.as_str()), (5, 9, expected));
+    assert_eq!(output, b"5\n");
+
+    let chunk = bytecode::Compiler::compile(&program);
+    let err = vm::Vm::new("3".as_bytes(), Vec::new()).run(&chunk).unwrap_err();
+    assert_eq!((err.span.line, err.span.col, err.message.as_str()), (5, 9, expected));
+    let err = vm::Vm::new("-1".as_bytes(), Vec::new()).run(&chunk).unwrap_err();
+    assert_eq!((err.span.line, err.message.as_str()), (2, "array size can't be negative: -1"));
+
+    let mut emitter = emitter::Emitter::new();
+    codegen::CGenerator::new(&mut emitter).with_bounds_checks("a.teeny").program(&program);
+    assert!(emitter.contents().contains("\n  printf(\"%d\\n\", v_a[tt_index(v_n, v_a_size, \"a\", \"a.teeny:5:9\")]);\n"));
+    let mut emitter = emitter::Emitter::new();
+    codegen::CGenerator::new(&mut emitter).program(&program);
+    assert!(emitter.contents().contains("\n  printf(\"%d\\n\", v_a[v_n]);\n"));
+    assert!(!emitter.contents().contains("tt_index"));
+}
+
+#[test]
+fn test_bytecode_round_trip() {
+    let source = String::from("PRINT \"hi\"\nINPUT n\nLABEL top\nLET n = twice(n) - 1.5 * 2\nIF n > 0 THEN\nGOTO top\nENDIF\n\
+        DIM a(3): INT\nLET a(0) = a(1) + 1\nFUNCTION twice(x)\nRETURN x * 2\nENDFUNCTION\n");
+    let program = parser::Parser::new(lexer::Lexer::build(&source)).program().unwrap();
+    let chunk = bytecode::Compiler::compile(&program);
+    let bytes = chunk.to_bytes();
+    assert_eq!(bytecode::Chunk::from_bytes(&bytes).unwrap(), chunk);
+    assert!(bytecode::Chunk::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+    let mut bad = chunk.clone();
+    bad.functions[0].address = 1000;
+    assert!(bytecode::Chunk::from_bytes(&bad.to_bytes()).is_err());
+    bad.functions = vec![chunk.functions[0].clone(), bytecode::Function { address: 1, ..chunk.functions[0].clone() }];
+    assert!(bytecode::Chunk::from_bytes(&bad.to_bytes()).is_err());
+}
+
+fn config(args: &[&str]) -> Result<Config, String> {
+    let args: Vec<String> = std::iter::once("teeny").chain(args.iter().copied()).map(String::from).collect();
+    Config::build(&args)
+}
+
+// Bytecode from a file may be anything; the VM has to stop with an error rather than panic.
+#[test]
+fn test_untrusted_bytecode() {
+    let chunk = bytecode::Chunk {
+        code: vec![bytecode::Op::Print, bytecode::Op::Halt],
+        spans: vec![token::Span::default(); 2],
+        ..bytecode::Chunk::default()
+    };
+    let chunk = bytecode::Chunk::from_bytes(&chunk.to_bytes()).unwrap();
+    let err = vm::Vm::new("".as_bytes(), Vec::new()).run(&chunk).unwrap_err();
+    assert_eq!(err.message, "invalid bytecode: popped an empty stack");
+
+    // Arrays and other variables can't be mixed up.
+    let program = parser::Parser::new(lexer::Lexer::build("LET n = 1\nDIM a(2): INT\nPRINT a(n)\n")).program().unwrap();
+    let chunk = bytecode::Compiler::compile(&program);
+    for (from, to) in [(bytecode::Op::LoadIndex(1), bytecode::Op::LoadIndex(0)), (bytecode::Op::Load(0), bytecode::Op::Load(1))] {
+        let mut bad = chunk.clone();
+        let at = bad.code.iter().position(|&op| op == from).unwrap();
+        bad.code[at] = to;
+        assert!(bytecode::Chunk::from_bytes(&bad.to_bytes()).is_err());
+    }
+    assert_eq!(value::Value::Int(1).element("n", value::Value::Int(0)), Err(String::from("n is not an array")));
+    let array = value::Value::dim(ast::Type::Int, value::Value::Int(2)).unwrap();
+    assert!(array.element("a", value::Value::Float(0.0)).is_err());
+}
+
+#[test]
+fn test_config() {
+    let default = config(&["examples/hello.teeny"]).unwrap();
+    assert_eq!(default.mode, Mode::Compile);
+    assert_eq!(default.emit, Emit::C);
+    assert_eq!(default.output, Destination::Path(String::from("examples/hello.c")));
+
+    assert_eq!(config(&["--emit", "tokens-json", "hello.teeny"]).unwrap().output, Destination::Path(String::from("hello.json")));
+    let tokens = config(&["--emit", "tokens", "hello.teeny", "-o", "-"]).unwrap();
+    assert_eq!(tokens.emit, Emit::Tokens);
+    assert_eq!(tokens.output, Destination::Stdout);
+    assert_eq!(config(&["--emit", "bytecode", "hello.teeny"]).unwrap().output, Destination::Path(String::from("hello.ttbc")));
+    assert_eq!(config(&["--vm", "hello.teeny"]).unwrap().mode, Mode::Vm);
+    assert_eq!(config(&["hello.teeny", "--help"]).unwrap().mode, Mode::Help);
+
+    let build = config(&["--run", "-O2", "hello.teeny"]).unwrap();
+    assert_eq!(build.mode, Mode::Build);
+    assert!(build.run);
+    assert_eq!(build.cflags, vec![String::from("-O2")]);
+    assert_eq!(build.output, Destination::Path(String::from("hello")));
+    assert!(config(&["--bounds-checks", "--build", "hello.teeny"]).unwrap().bounds_checks);
+    assert!(!build.bounds_checks);
+
+    assert!(config(&[]).is_err());
+    assert!(config(&["hello.teeny", "-o"]).is_err());
+    assert!(config(&["hello.teeny", "--emit", "wasm"]).is_err());
+    assert!(config(&["hello.teeny", "--interpret", "-o", "out.c"]).is_err());
+    assert!(config(&["hello.teeny", "--interpret", "--vm"]).is_err());
+    assert!(config(&["hello.c"]).is_err());
+    assert!(config(&["hello.teeny", "-O2"]).is_err());
+    assert!(config(&["hello.teeny", "--build", "-o", "-"]).is_err());
+    assert!(config(&["hello", "--build"]).is_err());
+    assert!(config(&["hello.teeny", "--vm", "--bounds-checks"]).is_err());
+    assert!(config(&["hello.teeny", "--emit", "ast", "--bounds-checks"]).is_err());
+}
+
+#[test]
+fn test_compile_str() {
+    let source = "DIM a(2): INT\nLET a(1) = 4\nPRINT a(1)\n";
+    let c = crate::compile_str(source, &Options::default()).unwrap();
+    let Output::Text(c) = c else { panic!("C should be text") };
+    assert!(c.contains("\n  printf(\"%d\\n\", v_a[1]);\n"));
+
+    let options = Options { file_name: String::from("lib.teeny"), line_directives: true, bounds_checks: true, ..Options::default() };
+    let Output::Text(c) = crate::compile_str(source, &options).unwrap() else { panic!("C should be text") };
+    assert!(c.contains("\n#line 3 \"lib.teeny\"\n  printf(\"%d\\n\", v_a[tt_index(1, v_a_size, \"a\", \"lib.teeny:3:9\")]);\n"));
+
+    let tokens = crate::compile_str("PRINT 1", &Options { emit: Emit::Tokens, ..Options::default() }).unwrap();
+    assert_eq!(tokens, Output::Text(String::from("1:1\tPRINT\t\"PRINT\"\n1:7\tNUMBER\t\"1\"\n1:8\tNEWLINE\t\"\\n\"\n2:1\tEOF\t\"\"\n")));
+    let Output::Bytes(bytes) = crate::compile_str(source, &Options { emit: Emit::Bytecode, ..Options::default() }).unwrap() else {
+        panic!("bytecode should be bytes")
+    };
+    let program = crate::parse_str(source, "lib.teeny").unwrap();
+    assert_eq!(bytecode::Chunk::from_bytes(&bytes).unwrap(), bytecode::Compiler::compile(&program));
+
+    let diagnostics = crate::compile_str("PRINT x\nLET y = @\n", &options).unwrap_err();
+    let messages: Vec<String> = diagnostics.iter().map(|err| err.to_string()).collect();
+    assert_eq!(messages, [
+        "lib.teeny:1:7: semantic error: Referencing variable before assignment: x",
+        "lib.teeny:2:9: lexical error: unknown character '@'",
+    ]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_run_passes_exit_status_through() {
+    use std::os::unix::process::ExitStatusExt;
+    let err = error::CompileError::Exited { executable: String::from("./prog"), status: std::process::ExitStatus::from_raw(3 << 8) };
+    assert_eq!((err.exit_code(), err.to_string()), (3, String::from("./prog exited with exit status: 3")));
+}
+
+#[test]
+fn test_line_directives() {
+    let source = String::from("LET a = 1\n\nPRINT a\n");
+    let program = parser::Parser::new(lexer::Lexer::build(&source)).program().unwrap();
+    let mut emitter = emitter::Emitter::new();
+    codegen::CGenerator::new(&mut emitter).with_line_directives("dir\\my \"file\".teeny").program(&program);
+    let contents = emitter.contents();
+    assert!(contents.contains("#line 1 \"dir\\\\my \\\"file\\\".teeny\"\n  v_a = 1;\n"));
+    assert!(contents.contains("#line 3 \"dir\\\\my \\\"file\\\".teeny\"\n  printf("));
+
+    let source = "IF 1 > 0 THEN\n  LET b = 2\nENDIF\n";
+    let program = parser::Parser::new(lexer::Lexer::build(source)).program().unwrap();
+    let mut emitter = emitter::Emitter::new();
+    codegen::CGenerator::new(&mut emitter).with_line_directives("b.teeny").program(&program);
+    assert!(emitter.contents().contains("#line 1 \"b.teeny\"\n  if(1>0){\n#line 2 \"b.teeny\"\n    int v_b = 0;\n#line 2 \"b.teeny\"\n    v_b = 2;\n"));
+
+    let source = "PRINT 1\nFUNCTION rewind(x)\nRETURN x\nENDFUNCTION\n";
+    let program = parser::Parser::new(lexer::Lexer::build(source)).program().unwrap();
+    let mut emitter = emitter::Emitter::new();
+    codegen::CGenerator::new(&mut emitter).with_line_directives("f.teeny").program(&program);
+    let contents = emitter.contents();
+    assert!(contents.contains("#line 2 \"f.teeny\"\ndouble f_rewind(double v_x);\n"));
+    assert!(contents.contains("#line 2 \"f.teeny\"\ndouble f_rewind(double v_x){\n"));
+}
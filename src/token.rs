@@ -1,11 +1,12 @@
 use crate::enums::TokenKind;
 
+use std::borrow::Cow;
 use std::fmt::Display;
 
 #[derive(Copy, Clone)]
 struct Keyword<'a>(&'a str, TokenKind);
 
-const KEYWORDS: [Keyword; 11] = [
+const KEYWORDS: [Keyword; 27] = [
     Keyword("LABEL", TokenKind::LABEL), 
     Keyword("GOTO", TokenKind::GOTO),
     Keyword("PRINT", TokenKind::PRINT),
@@ -16,27 +17,62 @@ const KEYWORDS: [Keyword; 11] = [
     Keyword("ENDIF", TokenKind::ENDIF),
     Keyword("WHILE", TokenKind::WHILE),
     Keyword("REPEAT", TokenKind::REPEAT),
-    Keyword("ENDWHILE", TokenKind::ENDWHILE)
+    Keyword("ENDWHILE", TokenKind::ENDWHILE),
+    Keyword("ELSE", TokenKind::ELSE),
+    Keyword("ELSEIF", TokenKind::ELSEIF),
+    Keyword("FOR", TokenKind::FOR),
+    Keyword("TO", TokenKind::TO),
+    Keyword("STEP", TokenKind::STEP),
+    Keyword("NEXT", TokenKind::NEXT),
+    Keyword("AND", TokenKind::AND),
+    Keyword("OR", TokenKind::OR),
+    Keyword("NOT", TokenKind::NOT),
+    Keyword("FUNCTION", TokenKind::FUNCTION),
+    Keyword("RETURN", TokenKind::RETURN),
+    Keyword("ENDFUNCTION", TokenKind::ENDFUNCTION),
+    Keyword("TRUE", TokenKind::TRUE),
+    Keyword("FALSE", TokenKind::FALSE),
+    Keyword("LEN", TokenKind::LEN),
+    Keyword("DIM", TokenKind::DIM)
 ];
 
-#[derive(Clone)]
-pub struct Token {
-  pub text: String,
-  pub kind: TokenKind
+/// Location of a token in the source: byte offset, 1-based line and column (counted in characters),
+/// and length in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+  pub offset: usize,
+  pub line: usize,
+  pub col: usize,
+  pub len: usize
 }
 
-impl Default for Token {
+impl Display for Span {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+      write!(f, "{}:{}", self.line, self.col)
+  }
+}
+
+/// A token. Its text borrows from the source unless it had to be decoded, as for a string
+/// literal with escapes.
+#[derive(Debug, Clone)]
+pub struct Token<'a> {
+  pub text: Cow<'a, str>,
+  pub kind: TokenKind,
+  pub span: Span
+}
+
+impl Default for Token<'_> {
   fn default() -> Self {
-      Self { text: Default::default(), kind: TokenKind::EOF }
+      Self { text: Default::default(), kind: TokenKind::EOF, span: Span::default() }
   }
 }
 
-impl Token {
-  pub fn check_if_keyword(token_text: &String) -> Option<TokenKind> {
+impl Token<'_> {
+  pub fn check_if_keyword(token_text: &str) -> Option<TokenKind> {
       let mut index = 0;
       while index < KEYWORDS.len() {
           if token_text == KEYWORDS[index].0 {
-              return Some(KEYWORDS[index].clone().1);
+              return Some(KEYWORDS[index].1);
           }
           index += 1;
       }
@@ -44,8 +80,46 @@ impl Token {
   }
 }
 
-impl Display for Token {
+impl Token<'_> {
+  // How the token should read in an error message.
+  pub fn describe(&self) -> String {
+      match self.kind {
+          TokenKind::NEWLINE => String::from("end of line"),
+          TokenKind::EOF => String::from("end of file"),
+          _ => self.text.to_string()
+      }
+  }
+
+  /// The token as a JSON object with its kind, text and span, as `--emit tokens-json` writes it.
+  pub fn to_json(&self) -> String {
+      format!(
+          "{{\"kind\": \"{:?}\", \"text\": {}, \"line\": {}, \"col\": {}, \"offset\": {}, \"len\": {}}}",
+          self.kind, json_string(&self.text), self.span.line, self.span.col, self.span.offset, self.span.len
+      )
+  }
+}
+
+// `text` as a JSON string literal.
+fn json_string(text: &str) -> String {
+  let mut json = String::with_capacity(text.len() + 2);
+  json.push('"');
+  for c in text.chars() {
+      match c {
+          '"' => json.push_str("\\\""),
+          '\\' => json.push_str("\\\\"),
+          '\n' => json.push_str("\\n"),
+          '\t' => json.push_str("\\t"),
+          '\r' => json.push_str("\\r"),
+          c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+          c => json.push(c)
+      }
+  }
+  json.push('"');
+  json
+}
+
+impl Display for Token<'_> {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-      write!(f, "{}, {:?}", self.text, self.kind)
+      write!(f, "{}, {:?} at {}", self.text, self.kind, self.span)
   }
 }
@@ -0,0 +1,91 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::CompileError;
+
+// Compilers tried in order when $CC isn't set.
+const CANDIDATES: [&str; 3] = ["cc", "gcc", "clang"];
+
+/// The command used to compile C: `$CC` if set (which may carry its own arguments, e.g.
+/// `ccache gcc`), otherwise the first of cc, gcc and clang found on `$PATH`.
+pub fn find_c_compiler() -> Option<Vec<String>> {
+  if let Ok(cc) = env::var("CC") {
+    let command: Vec<String> = cc.split_whitespace().map(String::from).collect();
+    if !command.is_empty() {
+      return Some(command);
+    }
+  }
+
+  CANDIDATES.iter()
+    .find(|name| find_on_path(name).is_some())
+    .map(|name| vec![String::from(*name)])
+}
+
+fn find_on_path(name: &str) -> Option<PathBuf> {
+  let path = env::var_os("PATH")?;
+  env::split_paths(&path)
+    .map(|dir| dir.join(name))
+    .find(|candidate| candidate.is_file())
+}
+
+/// Compile generated C into a native executable at `output`, passing `flags` through to the
+/// C compiler. The C is written to a temporary file that is removed afterwards.
+pub fn build(c_source: &str, output: &Path, flags: &[String]) -> Result<(), CompileError> {
+  let command = find_c_compiler().ok_or_else(|| {
+    CompileError::Io(io::Error::new(io::ErrorKind::NotFound, "no C compiler found; install cc, gcc or clang or set $CC"))
+  })?;
+
+  let c_path = temp_c_path(output);
+  fs::write(&c_path, c_source).map_err(|why| {
+    CompileError::Io(io::Error::new(why.kind(), format!("couldn't write to {}: {}", c_path.display(), why)))
+  })?;
+
+  let result = Command::new(&command[0])
+    .args(&command[1..])
+    .args(flags)
+    .arg(&c_path)
+    .arg("-o")
+    .arg(output)
+    .output();
+  let _ = fs::remove_file(&c_path);
+
+  let result = result.map_err(|why| {
+    CompileError::Io(io::Error::new(why.kind(), format!("couldn't run {}: {}", command[0], why)))
+  })?;
+  if !result.status.success() {
+    return Err(CompileError::CCompiler {
+      command: command.join(" "),
+      stderr: String::from_utf8_lossy(&result.stderr).into_owned()
+    });
+  }
+  Ok(())
+}
+
+// A file name in the temp directory that won't collide with a parallel build.
+fn temp_c_path(output: &Path) -> PathBuf {
+  let stem = output.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+  let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.subsec_nanos()).unwrap_or(0);
+  env::temp_dir().join(format!("teeny-{}-{}-{}.c", stem, process::id(), nanos))
+}
+
+/// Run a built executable with the compiler's stdin and stdout.
+pub fn run(executable: &Path) -> Result<(), CompileError> {
+  // A bare file name would be looked up on $PATH rather than in the current directory.
+  let executable = if executable.components().count() == 1 {
+    Path::new(".").join(executable)
+  } else {
+    executable.to_path_buf()
+  };
+
+  let status = Command::new(&executable).status().map_err(|why| {
+    CompileError::Io(io::Error::new(why.kind(), format!("couldn't run {}: {}", executable.display(), why)))
+  })?;
+  if !status.success() {
+    return Err(CompileError::Exited { executable: executable.display().to_string(), status });
+  }
+  Ok(())
+}
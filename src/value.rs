@@ -0,0 +1,303 @@
+use crate::ast::{BinaryOp, CompareOp, Type, UnaryOp, Variable};
+
+use std::io::{self, BufRead};
+use std::rc::Rc;
+
+/// A value produced while evaluating an expression, one variant per `Type`, or the contents of an
+/// array variable.
+///
+/// Executing a program directly has to print exactly what the generated C would, so values follow
+/// C's rules: an INT is an `int` and a FLOAT a `double`, and mixed operands are promoted the same
+/// way C promotes them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Int(i32),
+  Float(f64),
+  Bool(bool),
+  Str(Rc<str>),
+  // The type of the elements, and the elements. Only ever held by a variable.
+  Array(Type, Vec<Value>)
+}
+
+impl Value {
+  // Parse a NUMBER token the way a C compiler reads the literal.
+  pub fn from_literal(text: &str) -> Value {
+    match Type::of_literal(text) {
+      Type::Int => Value::Int(text.parse().unwrap()),
+      _ => Value::Float(text.parse::<f64>().unwrap_or(0.0))
+    }
+  }
+
+  /// The value a variable of type `ty` starts with.
+  pub fn zero(ty: Type) -> Value {
+    match ty {
+      Type::Int => Value::Int(0),
+      Type::Float => Value::Float(0.0),
+      Type::Bool => Value::Bool(false),
+      Type::String => Value::Str(Rc::from(""))
+    }
+  }
+
+  /// What `variable` holds before it is first assigned: its type's zero, or an array with no
+  /// elements until its DIM runs.
+  pub fn initial(variable: &Variable) -> Value {
+    if variable.array {
+      Value::Array(variable.ty, Vec::new())
+    } else {
+      Value::zero(variable.ty)
+    }
+  }
+
+  /// The type of the value, or of an array's elements.
+  pub fn ty(&self) -> Type {
+    match self {
+      Value::Int(_) => Type::Int,
+      Value::Float(_) => Type::Float,
+      Value::Bool(_) => Type::Bool,
+      Value::Str(_) => Type::String,
+      Value::Array(ty, _) => *ty
+    }
+  }
+
+  /// Convert for storage in a variable of type `ty`, which the checker has made sure accepts it.
+  pub fn convert(self, ty: Type) -> Value {
+    match (self, ty) {
+      (Value::Int(value), Type::Float) => Value::Float(value as f64),
+      (value, _) => value
+    }
+  }
+
+  // Numbers for arithmetic. Checked programs never do arithmetic on anything else.
+  fn as_f64(&self) -> f64 {
+    match *self {
+      Value::Int(value) => value as f64,
+      Value::Float(value) => value,
+      Value::Bool(value) => value as i32 as f64,
+      Value::Str(_) | Value::Array(..) => 0.0
+    }
+  }
+
+  pub fn is_truthy(&self) -> bool {
+    match self {
+      Value::Bool(value) => *value,
+      value => value.as_f64() != 0.0
+    }
+  }
+
+  pub fn unary(op: UnaryOp, operand: Value) -> Value {
+    match (op, operand) {
+      (UnaryOp::Minus, Value::Int(value)) => Value::Int(value.wrapping_neg()),
+      (UnaryOp::Minus, Value::Float(value)) => Value::Float(-value),
+      (_, value) => value
+    }
+  }
+
+  /// Apply an arithmetic operator or join two strings, or return `None` for integer division by zero.
+  pub fn binary(op: BinaryOp, lhs: Value, rhs: Value) -> Option<Value> {
+    match (&lhs, &rhs) {
+      // The checker only lets `+` through for strings.
+      (Value::Str(a), Value::Str(b)) => Some(Value::Str(Rc::from(format!("{}{}", a, b)))),
+      (&Value::Int(a), &Value::Int(b)) => match op {
+        BinaryOp::Add => Some(Value::Int(a.wrapping_add(b))),
+        BinaryOp::Sub => Some(Value::Int(a.wrapping_sub(b))),
+        BinaryOp::Mul => Some(Value::Int(a.wrapping_mul(b))),
+        BinaryOp::Div if b == 0 => None,
+        BinaryOp::Div => Some(Value::Int(a.wrapping_div(b)))
+      },
+      _ => {
+        let (a, b) = (lhs.as_f64(), rhs.as_f64());
+        Some(Value::Float(match op {
+          BinaryOp::Add => a + b,
+          BinaryOp::Sub => a - b,
+          BinaryOp::Mul => a * b,
+          BinaryOp::Div => a / b
+        }))
+      }
+    }
+  }
+
+  pub fn compare(op: CompareOp, lhs: Value, rhs: Value) -> Value {
+    let result = match (&lhs, &rhs) {
+      (Value::Int(a), Value::Int(b)) => compare_ordered(op, a, b),
+      (Value::Bool(a), Value::Bool(b)) => compare_ordered(op, a, b),
+      (Value::Str(a), Value::Str(b)) => compare_ordered(op, a, b),
+      _ => compare_ordered(op, lhs.as_f64(), rhs.as_f64())
+    };
+    Value::Bool(result)
+  }
+
+  /// `DIM`: a new array of `size` zeros of type `ty`.
+  pub fn dim(ty: Type, size: Value) -> Result<Value, String> {
+    let size = size.as_index()?;
+    if size < 0 {
+      return Err(format!("array size can't be negative: {}", size));
+    }
+    Ok(Value::Array(ty, vec![Value::zero(ty); size as usize]))
+  }
+
+  /// Element `index` of the array `name`, which this is.
+  pub fn element(&self, name: &str, index: Value) -> Result<Value, String> {
+    let Value::Array(_, elements) = self else {
+      return Err(not_an_array(name));
+    };
+    let index = index.as_index()?;
+    usize::try_from(index).ok().and_then(|at| elements.get(at)).cloned().ok_or_else(|| out_of_bounds(name, index, elements.len()))
+  }
+
+  /// Assign element `index` of the array `name`, which this is, converting to the element type.
+  pub fn set_element(&mut self, name: &str, index: Value, value: Value) -> Result<(), String> {
+    let Value::Array(ty, elements) = self else {
+      return Err(not_an_array(name));
+    };
+    let index = index.as_index()?;
+    let size = elements.len();
+    let element = usize::try_from(index).ok().and_then(|at| elements.get_mut(at)).ok_or_else(|| out_of_bounds(name, index, size))?;
+    *element = value.convert(*ty);
+    Ok(())
+  }
+
+  // The checker only allows INT sizes and indices, but loaded bytecode might use anything.
+  fn as_index(&self) -> Result<i32, String> {
+    match *self {
+      Value::Int(value) => Ok(value),
+      _ => Err(String::from("array size or index must be an INT"))
+    }
+  }
+
+  /// `LEN(s)`: the length of a string in bytes, as C's `strlen` counts it.
+  pub fn length(operand: Value) -> Value {
+    match operand {
+      Value::Str(value) => Value::Int(value.len() as i32),
+      _ => Value::Int(0)
+    }
+  }
+}
+
+// The runtime error for indexing a variable that isn't an array, which only loaded bytecode can do.
+fn not_an_array(name: &str) -> String {
+  format!("{} is not an array", name)
+}
+
+// The runtime error for an index outside an array, as the generated C's `tt_index` words it.
+fn out_of_bounds(name: &str, index: i32, size: usize) -> String {
+  format!("index {} is out of bounds for {}, whose size is {}", index, name, size)
+}
+
+fn compare_ordered<T: PartialOrd>(op: CompareOp, a: T, b: T) -> bool {
+  match op {
+    CompareOp::Eq => a == b,
+    CompareOp::NotEq => a != b,
+    CompareOp::Lt => a < b,
+    CompareOp::LtEq => a <= b,
+    CompareOp::Gt => a > b,
+    CompareOp::GtEq => a >= b
+  }
+}
+
+/// Format a value the way the generated `printf` does for its type, without the newline: `%d` for
+/// an INT, `%.2f` for a FLOAT, `true` or `false` for a BOOL and `%s` for a STRING.
+pub fn format_value(value: &Value) -> String {
+  match value {
+    Value::Int(value) => value.to_string(),
+    Value::Float(value) if value.is_nan() => String::from(if value.is_sign_negative() { "-nan" } else { "nan" }),
+    Value::Float(value) if value.is_infinite() => String::from(if *value < 0.0 { "-inf" } else { "inf" }),
+    Value::Float(value) => format!("{:.2}", value),
+    Value::Bool(value) => value.to_string(),
+    Value::Str(value) => value.to_string(),
+    Value::Array(..) => unreachable!("the checker doesn't let whole arrays be printed")
+  }
+}
+
+/// Read a value for `INPUT` into a variable of type `ty`, with `scan_line` for a STRING and
+/// `scan_number` for anything else.
+pub fn scan<R: BufRead>(input: &mut R, ty: Type) -> io::Result<Option<Value>> {
+  if ty == Type::String {
+    scan_line(input)
+  } else {
+    scan_number(input, ty)
+  }
+}
+
+/// Read a string the way the generated `tt_input` does: skip leading whitespace, including the
+/// newline a previous INPUT left behind, then take the rest of the line without its newline.
+/// Returns `None` at end of input, where the variable is left as it was.
+pub fn scan_line<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+  skip_whitespace(input)?;
+  if peek_byte(input)?.is_none() {
+    return Ok(None);
+  }
+
+  let mut line = Vec::new();
+  input.read_until(b'\n', &mut line)?;
+  if line.last() == Some(&b'\n') {
+    line.pop();
+  }
+  Ok(Some(Value::Str(Rc::from(String::from_utf8_lossy(&line).as_ref()))))
+}
+
+/// Read a number of type `ty` the way the generated `scanf("%d", ...)` or `scanf("%lf", ...)` does:
+/// skip leading whitespace, then take the longest prefix that looks like a number, leaving the rest
+/// of the input for the next read. If nothing numeric is there the word is discarded (the generated
+/// `scanf("%*s")`) and reads as 0. Returns `None` at end of input, where C leaves the variable untouched.
+pub fn scan_number<R: BufRead>(input: &mut R, ty: Type) -> io::Result<Option<Value>> {
+  skip_whitespace(input)?;
+  if peek_byte(input)?.is_none() {
+    return Ok(None);
+  }
+
+  let mut text = String::new();
+  while let Some(byte) = peek_byte(input)? {
+    let has_exponent = text.contains(['e', 'E']);
+    let integer = ty == Type::Int;
+    let accepted = match byte {
+      b'0'..=b'9' => true,
+      b'+' | b'-' => text.is_empty() || text.ends_with(['e', 'E']),
+      b'.' => !integer && !text.contains('.') && !has_exponent,
+      b'e' | b'E' => !integer && !has_exponent && text.contains(|c: char| c.is_ascii_digit()),
+      _ => false
+    };
+    if !accepted {
+      break;
+    }
+    text.push(byte as char);
+    input.consume(1);
+  }
+
+  // Drop a dangling exponent or sign, as in "2e" or "3e+".
+  let number = text.trim_end_matches(['e', 'E', '+', '-']);
+  let value = if ty == Type::Int {
+    // Out of range, C's strtol clamps to a `long` that is then cut down to the `int`.
+    number.parse::<i64>()
+      .or_else(|err| if number.is_empty() { Err(err) } else { Ok(if number.starts_with('-') { i64::MIN } else { i64::MAX }) })
+      .map(|value| Value::Int(value as i32))
+      .ok()
+  } else {
+    number.parse::<f64>().map(Value::Float).ok()
+  };
+  match value {
+    Some(value) => Ok(Some(value)),
+    None => {
+      while let Some(byte) = peek_byte(input)? {
+        if byte.is_ascii_whitespace() {
+          break;
+        }
+        input.consume(1);
+      }
+      Ok(Some(Value::zero(ty)))
+    }
+  }
+}
+
+fn skip_whitespace<R: BufRead>(input: &mut R) -> io::Result<()> {
+  while let Some(byte) = peek_byte(input)? {
+    if !byte.is_ascii_whitespace() {
+      break;
+    }
+    input.consume(1);
+  }
+  Ok(())
+}
+
+fn peek_byte<R: BufRead>(input: &mut R) -> io::Result<Option<u8>> {
+  Ok(input.fill_buf()?.first().copied())
+}
@@ -0,0 +1,163 @@
+use std::io::{BufRead, Write};
+
+use crate::ast::{BinaryOp, UnaryOp};
+use crate::bytecode::{Chunk, Op};
+use crate::error::RuntimeError;
+use crate::token::Span;
+use crate::value::{self, Value};
+
+/// How deeply FUNCTION calls may nest before the program is stopped with a stack overflow, about
+/// where the compiled C would crash with its default 8 MiB stack. The frames are on the heap.
+const MAX_CALL_DEPTH: usize = 100_000;
+
+/// A stack machine that executes a compiled `Chunk`.
+pub struct Vm<R: BufRead, W: Write> {
+  input: R,
+  output: W,
+  stack: Vec<Value>
+}
+
+// A suspended caller: where to continue and the variables it had, and the function it called.
+struct Frame {
+  return_address: usize,
+  slots: Vec<Value>,
+  function: usize
+}
+
+impl<R: BufRead, W: Write> Vm<R, W> {
+  pub fn new(input: R, output: W) -> Vm<R, W> {
+    Vm {
+      input,
+      output,
+      stack: Vec::new()
+    }
+  }
+
+  pub fn run(&mut self, chunk: &Chunk) -> Result<(), RuntimeError> {
+    // Variables start at zero like those in the generated C.
+    let mut slots: Vec<Value> = chunk.slots.iter().map(Value::initial).collect();
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut pc = 0;
+    self.stack.clear();
+
+    loop {
+      let op = chunk.code[pc];
+      let span = chunk.spans[pc];
+      pc += 1;
+
+      match op {
+        Op::Constant(index) => self.stack.push(chunk.constants[index].clone()),
+        Op::Load(slot) => self.stack.push(slots[slot].clone()),
+        Op::Store(slot) => {
+          // A slot keeps the type of the zero it started with.
+          let ty = slots[slot].ty();
+          slots[slot] = self.pop(span)?.convert(ty);
+        },
+        Op::Dim(slot) => {
+          // An array slot always holds an array, which knows its element type.
+          let size = self.pop(span)?;
+          slots[slot] = Value::dim(slots[slot].ty(), size).map_err(|message| RuntimeError { span, message })?;
+        },
+        Op::LoadIndex(slot) => {
+          let index = self.pop(span)?;
+          let element = slots[slot].element(slot_name(chunk, &frames, slot), index);
+          self.stack.push(element.map_err(|message| RuntimeError { span, message })?);
+        },
+        Op::StoreIndex(slot) => {
+          let value = self.pop(span)?;
+          let index = self.pop(span)?;
+          slots[slot].set_element(slot_name(chunk, &frames, slot), index, value).map_err(|message| RuntimeError { span, message })?;
+        },
+        Op::Negate => {
+          let operand = self.pop(span)?;
+          self.stack.push(Value::unary(UnaryOp::Minus, operand));
+        },
+        Op::Length => {
+          let operand = self.pop(span)?;
+          self.stack.push(Value::length(operand));
+        },
+        Op::Add | Op::Subtract | Op::Multiply | Op::Divide => {
+          let rhs = self.pop(span)?;
+          let lhs = self.pop(span)?;
+          let op = match op {
+            Op::Add => BinaryOp::Add,
+            Op::Subtract => BinaryOp::Sub,
+            Op::Multiply => BinaryOp::Mul,
+            _ => BinaryOp::Div
+          };
+          let result = Value::binary(op, lhs, rhs).ok_or_else(|| RuntimeError {
+            span,
+            message: String::from("integer division by zero")
+          })?;
+          self.stack.push(result);
+        },
+        Op::Compare(op) => {
+          let rhs = self.pop(span)?;
+          let lhs = self.pop(span)?;
+          self.stack.push(Value::compare(op, lhs, rhs));
+        },
+        Op::Jump(address) => pc = address,
+        Op::JumpIfFalse(address) => {
+          if !self.pop(span)?.is_truthy() {
+            pc = address;
+          }
+        },
+        Op::Print => {
+          let value = self.pop(span)?;
+          writeln!(self.output, "{}", value::format_value(&value)).map_err(|err| io_error(span, err))?;
+        },
+        Op::PrintText(index) => {
+          writeln!(self.output, "{}", chunk.strings[index]).map_err(|err| io_error(span, err))?;
+        },
+        Op::Input(slot) => {
+          self.output.flush().map_err(|err| io_error(span, err))?;
+          // At end of input scanf leaves the variable as it was.
+          let ty = slots[slot].ty();
+          if let Some(value) = value::scan(&mut self.input, ty).map_err(|err| io_error(span, err))? {
+            slots[slot] = value;
+          }
+        },
+        Op::Call(index) => {
+          let function = &chunk.functions[index];
+          if frames.len() == MAX_CALL_DEPTH {
+            return Err(RuntimeError { span, message: format!("stack overflow calling {}", function.name) });
+          }
+          let mut locals: Vec<Value> = function.slots.iter().map(Value::initial).collect();
+          for param in (0..function.params).rev() {
+            locals[param] = self.pop(span)?.convert(function.slots[param].ty);
+          }
+          frames.push(Frame { return_address: pc, slots: std::mem::replace(&mut slots, locals), function: index });
+          pc = function.address;
+        },
+        Op::Return => {
+          // Like the C function, the result has the declared type.
+          let frame = frames.pop().expect("bytecode returned from the main program");
+          let result = self.pop(span)?.convert(chunk.functions[frame.function].ret);
+          slots = frame.slots;
+          pc = frame.return_address;
+          self.stack.push(result);
+        },
+        Op::Halt => break
+      }
+    }
+
+    self.output.flush().map_err(|err| io_error(Span::default(), err))
+  }
+
+  // Compiled code always has an operand ready, but a loaded file might not.
+  fn pop(&mut self, span: Span) -> Result<Value, RuntimeError> {
+    self.stack.pop().ok_or_else(|| RuntimeError { span, message: String::from("invalid bytecode: popped an empty stack") })
+  }
+}
+
+// The name of the variable in `slot` of the running function or main program, for error messages.
+fn slot_name<'a>(chunk: &'a Chunk, frames: &[Frame], slot: usize) -> &'a str {
+  match frames.last() {
+    Some(frame) => &chunk.functions[frame.function].slots[slot].name,
+    None => &chunk.slots[slot].name
+  }
+}
+
+fn io_error(span: Span, err: std::io::Error) -> RuntimeError {
+  RuntimeError { span, message: format!("I/O error: {}", err) }
+}
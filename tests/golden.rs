@@ -0,0 +1,130 @@
+// End-to-end tests over the programs in tests/golden. Each `name.teeny` is run with `name.in`
+// (if present) on stdin and must print exactly `name.out`: through the generated C built with
+// the local C compiler, through the interpreter and through the bytecode VM.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// The compiler's own binary, which each backend is run through as a user would.
+const COMPILER: &str = env!("CARGO_BIN_EXE_teeny_tiny_compiler");
+
+struct Case {
+    name: String,
+    source: PathBuf,
+    input: Vec<u8>,
+    expected: String,
+}
+
+fn cases() -> Vec<Case> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+    let mut cases: Vec<Case> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "teeny"))
+        .map(|source| {
+            let name = source.file_stem().unwrap().to_string_lossy().into_owned();
+            let expected = fs::read_to_string(source.with_extension("out"))
+                .unwrap_or_else(|_| panic!("{} has no .out file", name));
+            let input = fs::read(source.with_extension("in")).unwrap_or_default();
+            Case { name, source, input, expected }
+        })
+        .collect();
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    assert!(!cases.is_empty(), "no golden programs found in {}", dir.display());
+    cases
+}
+
+// Run the compiler with `args` and the case's input on stdin, returning what it prints.
+fn run(case: &Case, backend: &str, args: &[&str], envs: &[(&str, &str)]) -> String {
+    let mut child = Command::new(COMPILER)
+        .args(args)
+        .arg(&case.source)
+        .envs(envs.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&case.input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{} ({}) exited with {}:\n{}",
+        case.name, backend, output.status, String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// The C compiler `--build` would use: `$CC`, or the first of cc, gcc and clang that runs.
+fn c_compiler() -> Option<String> {
+    if let Ok(cc) = env::var("CC") {
+        if !cc.trim().is_empty() {
+            return Some(cc);
+        }
+    }
+    ["cc", "gcc", "clang"]
+        .iter()
+        .find(|name| Command::new(name).arg("--version").output().is_ok_and(|output| output.status.success()))
+        .map(|name| name.to_string())
+}
+
+fn check(failures: &mut Vec<String>, case: &Case, backend: &str, actual: String) {
+    if actual != case.expected {
+        failures.push(format!(
+            "{} ({}):\n--- expected\n{}--- actual\n{}",
+            case.name, backend, case.expected, actual
+        ));
+    }
+}
+
+#[test]
+fn golden_c() {
+    // Without a C compiler the generated C goes untested, so that has to be asked for.
+    let Some(cc) = c_compiler() else {
+        assert!(
+            env::var_os("TEENY_SKIP_CC").is_some(),
+            "no C compiler found; set CC, or set TEENY_SKIP_CC=1 to skip the C golden tests"
+        );
+        eprintln!("skipping golden_c: no C compiler found and TEENY_SKIP_CC is set");
+        return;
+    };
+    // Hold the C to the standard, whatever the compiler defaults to.
+    let cc = format!("{} -std=c99 -pedantic-errors", cc);
+
+    let dir = env::temp_dir().join(format!("teeny-golden-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut failures = Vec::new();
+    for case in cases() {
+        let executable = dir.join(&case.name);
+        let args = ["--run", "--bounds-checks", "-o", executable.to_str().unwrap()];
+        let actual = run(&case, "C", &args, &[("CC", &cc)]);
+        check(&mut failures, &case, "C", actual);
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn golden_interpreter() {
+    let mut failures = Vec::new();
+    for case in cases() {
+        let actual = run(&case, "interpreter", &["--interpret"], &[]);
+        check(&mut failures, &case, "interpreter", actual);
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn golden_vm() {
+    let mut failures = Vec::new();
+    for case in cases() {
+        let actual = run(&case, "vm", &["--vm"], &[]);
+        check(&mut failures, &case, "vm", actual);
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}